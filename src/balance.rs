@@ -0,0 +1,62 @@
+use crate::data::Dataset;
+use crate::matching::Match;
+use crate::stats::sample_variance;
+use serde::Serialize;
+use statistical::mean;
+
+/// Balance of one matching covariate in one iteration, before matching (all
+/// records) and after (matched records, controls averaged per match). Both
+/// standardized mean differences use the pre-matching pooled standard
+/// deviation, so they differ only through the means. Variance ratios are
+/// Small-Group over Big-Group, and NaN with fewer than two records on a side.
+#[derive(Debug, Serialize, Clone)]
+pub struct Balance {
+  pub arm: String,
+  pub iteration: usize,
+  pub covariate: String,
+  pub smd_before: f64,
+  pub smd_after: f64,
+  pub variance_ratio_before: f64,
+  pub variance_ratio_after: f64,
+}
+
+// The balance of covariate `k` of `dataset` before and after `matches`.
+pub(crate) fn covariate_balance(
+  iteration: usize,
+  k: usize,
+  dataset: &Dataset,
+  matches: &[Match],
+) -> Balance {
+  let small_before = dataset
+    .small
+    .iter()
+    .map(|e| e.covariates[k])
+    .collect::<Vec<f64>>();
+  let big_before = dataset
+    .big
+    .iter()
+    .map(|e| e.covariates[k])
+    .collect::<Vec<f64>>();
+  let small_after = matches
+    .iter()
+    .map(|e| dataset.small[e.small].covariates[k])
+    .collect::<Vec<f64>>();
+  let big_after = matches
+    .iter()
+    .map(|e| e.big_mean(&dataset.big, |b| b.covariates[k]))
+    .collect::<Vec<f64>>();
+
+  let pooled_stdev = ((sample_variance(&small_before) + sample_variance(&big_before)) / 2.0).sqrt();
+  let smd = |small: &[f64], big: &[f64]| (mean(small) - mean(big)) / pooled_stdev;
+  let variance_ratio = |small: &[f64], big: &[f64]| sample_variance(small) / sample_variance(big);
+
+  Balance {
+    arm: dataset.arm.clone(),
+    iteration,
+    covariate: dataset.covariates[k].clone(),
+    smd_before: smd(&small_before, &big_before),
+    smd_after: smd(&small_after, &big_after),
+    variance_ratio_before: variance_ratio(&small_before, &big_before),
+    variance_ratio_after: variance_ratio(&small_after, &big_after),
+  }
+}
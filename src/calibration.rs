@@ -0,0 +1,121 @@
+use crate::data::Dataset;
+use crate::distance::Metric;
+use crate::error::Result;
+use crate::run::{run, significance, Output, Settings};
+use crate::stats::Test;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::Serialize;
+use statistical::{mean, standard_deviation};
+use std::cmp::Ordering;
+
+/// The proportion significant of one test of one outcome, at one threshold, in
+/// one null replicate.
+#[derive(Debug, Serialize, Clone)]
+pub struct NullProportion {
+  pub arm: String,
+  pub replicate: usize,
+  /// Seeds the label shuffle of this replicate.
+  pub seed: u64,
+  pub outcome: String,
+  pub test: Test,
+  pub alpha: f64,
+  pub proportion_significant: f64,
+}
+
+/// The null distribution of one proportion significant, over the replicates
+/// of `calibrate`.
+#[derive(Debug, Serialize, Clone)]
+pub struct NullSummary {
+  pub replicates: usize,
+  pub mean: f64,
+  pub stdev: f64,
+  /// 95th percentile (nearest rank).
+  pub q95: f64,
+  /// Share of replicates at least as high as the observed proportion,
+  /// counting the observed one among them.
+  pub pvalue: f64,
+}
+
+impl NullSummary {
+  /// Summarizes the null `proportions` and places `observed` among them.
+  pub fn new(observed: f64, proportions: &[f64]) -> NullSummary {
+    let mut sorted = proportions.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let at_least = proportions.iter().filter(|p| **p >= observed).count();
+
+    NullSummary {
+      replicates: proportions.len(),
+      mean: mean(proportions),
+      stdev: if proportions.len() > 1 {
+        standard_deviation(proportions, None)
+      } else {
+        f64::NAN
+      },
+      q95: sorted[((0.95 * sorted.len() as f64).ceil() as usize).max(1) - 1],
+      pvalue: (at_least + 1) as f64 / (proportions.len() + 1) as f64,
+    }
+  }
+}
+
+/// Calibrates the proportions significant of `dataset` under no true effect.
+/// Each replicate shuffles the records between the Small-Group and the
+/// Big-Group with its seed from `replicate_seeds`, keeping their sizes, places
+/// them again with `metric`, and reruns every iteration of `seeds` on them,
+/// counting p-values below each of `alphas` as significant. The caliper stays
+/// as resolved for the real groups. Fails if a replicate's propensity model
+/// does not converge.
+pub fn calibrate(
+  dataset: &Dataset,
+  settings: &Settings,
+  metric: Metric,
+  seeds: &[u64],
+  replicate_seeds: &[u64],
+  alphas: &[f64],
+) -> Result<Vec<NullProportion>> {
+  // The pairs and controls of the replicates are never reported
+  let settings = Settings {
+    pairs: false,
+    reuse: false,
+    ..settings.clone()
+  };
+  let mut proportions: Vec<NullProportion> = Vec::new();
+  for (replicate, &seed) in replicate_seeds.iter().enumerate() {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut pooled = dataset
+      .small
+      .iter()
+      .chain(dataset.big.iter())
+      .cloned()
+      .collect::<Vec<_>>();
+    pooled.shuffle(&mut rng);
+    let big = pooled.split_off(dataset.small.len());
+
+    let mut null = Dataset {
+      arm: dataset.arm.clone(),
+      small: pooled,
+      big,
+      covariates: dataset.covariates.clone(),
+      outcomes: dataset.outcomes.clone(),
+    };
+    null.place(metric)?;
+
+    let outputs: Vec<Output> = run(&null, &settings, seeds)?;
+    for k in 0..outputs[0].outcomes.len() {
+      for tested in significance(&outputs, k, alphas) {
+        proportions.push(NullProportion {
+          arm: dataset.arm.clone(),
+          replicate,
+          seed,
+          outcome: dataset.outcomes[k].clone(),
+          test: tested.test,
+          alpha: tested.alpha,
+          proportion_significant: tested.proportion_significant,
+        });
+      }
+    }
+  }
+
+  Ok(proportions)
+}
@@ -0,0 +1,329 @@
+use crate::data::{Dataset, Instance};
+use crate::stats::{weighted_t, Alternative};
+use serde::Serialize;
+use statistical::standard_deviation;
+use std::collections::HashMap;
+
+/// The result of coarsened exact matching. Small-Group records in matched
+/// strata have weight 1; Big-Group records get the usual CEM weights so each
+/// stratum contributes in proportion to its Small-Group count. Every outcome
+/// is compared with a weighted least-squares t-test.
+#[derive(Debug, Serialize, Clone)]
+pub struct CemOutput {
+  pub arm: String,
+  pub strata: usize,
+  pub matched_strata: usize,
+  pub small_matched: usize,
+  pub small_unmatched: usize,
+  pub big_matched: usize,
+  pub big_unmatched: usize,
+
+  /// One entry per outcome, in schema order.
+  pub outcomes: Vec<CemOutcome>,
+  pub alternative: Alternative,
+
+  pub l1_before: f64,
+  pub l1_after: f64,
+}
+
+/// Weighted means of one outcome over the records in matched strata, and the
+/// weighted t-test between them.
+#[derive(Debug, Serialize, Clone)]
+pub struct CemOutcome {
+  pub outcome: String,
+  /// Records with the outcome and a nonzero weight, in both groups; the
+  /// t-test needs at least three.
+  pub observations: usize,
+  pub small_mean: f64,
+  pub big_weighted_mean: f64,
+  pub difference: f64,
+  pub t_pvalue: f64,
+  pub t_tvalue: f64,
+}
+
+impl CemOutcome {
+  /// Every statistic by name, in the order of the CEM results file.
+  pub fn statistics(&self) -> Vec<(&'static str, f64)> {
+    vec![
+      ("observations", self.observations as f64),
+      ("small_mean", self.small_mean),
+      ("big_weighted_mean", self.big_weighted_mean),
+      ("difference", self.difference),
+      ("t_pvalue", self.t_pvalue),
+      ("t_tvalue", self.t_tvalue),
+    ]
+  }
+}
+
+impl CemOutput {
+  /// Column names of `record`: the fixed columns, with `<outcome>_<statistic>`
+  /// for every outcome after the counts.
+  pub fn headers(&self) -> Vec<String> {
+    let mut headers = [
+      "arm",
+      "strata",
+      "matched_strata",
+      "small_matched",
+      "small_unmatched",
+      "big_matched",
+      "big_unmatched",
+    ]
+    .iter()
+    .map(|h| h.to_string())
+    .collect::<Vec<String>>();
+    for outcome in self.outcomes.iter() {
+      for (name, _) in outcome.statistics() {
+        headers.push(format!("{}_{}", outcome.outcome, name));
+      }
+    }
+    headers.extend(
+      ["alternative", "l1_before", "l1_after"]
+        .iter()
+        .map(|h| h.to_string()),
+    );
+
+    headers
+  }
+
+  /// The result as one row of the CEM results file in CSV.
+  pub fn record(&self) -> Vec<String> {
+    let mut record = vec![
+      self.arm.clone(),
+      self.strata.to_string(),
+      self.matched_strata.to_string(),
+      self.small_matched.to_string(),
+      self.small_unmatched.to_string(),
+      self.big_matched.to_string(),
+      self.big_unmatched.to_string(),
+    ];
+    for outcome in self.outcomes.iter() {
+      for (_, value) in outcome.statistics() {
+        record.push(value.to_string());
+      }
+    }
+    record.push(self.alternative.to_string());
+    record.push(self.l1_before.to_string());
+    record.push(self.l1_after.to_string());
+
+    record
+  }
+}
+
+// Equal-width bins spanning every record's value of covariate `k`, as
+// (lowest value, bin width, number of bins). No bins is taken as one.
+fn equal_width_bins(records: &[&Instance], k: usize, bins: usize) -> (f64, f64, usize) {
+  let bins = bins.max(1);
+  let values = records.iter().map(|e| e.covariates[k]);
+  let low = values.clone().fold(f64::INFINITY, f64::min);
+  let high = values.fold(f64::NEG_INFINITY, f64::max);
+
+  (low, (high - low) / bins as f64, bins)
+}
+
+// The cell a record falls in once each covariate is cut into its bins and
+// its stratum is taken as it is.
+fn coarsened_cell(record: &Instance, cuts: &[(f64, f64, usize)]) -> String {
+  let mut cell = record
+    .covariates
+    .iter()
+    .zip(cuts.iter())
+    .map(|(value, (low, width, bins))| {
+      let bin = if *width > 0.0 {
+        ((value - low) / width).floor() as usize
+      } else {
+        0
+      };
+      bin.min(bins - 1).to_string()
+    })
+    .collect::<Vec<String>>();
+  cell.push(record.stratum.clone());
+
+  cell.join("/")
+}
+
+// Multivariate L1 imbalance: half the summed absolute difference between the
+// groups' weighted relative frequencies over the cells.
+fn l1_imbalance(small_boys: &[(String, f64)], big_boys: &[(String, f64)]) -> f64 {
+  let mut frequencies: HashMap<&str, (f64, f64)> = HashMap::new();
+  let small_total: f64 = small_boys.iter().map(|(_, w)| w).sum();
+  let big_total: f64 = big_boys.iter().map(|(_, w)| w).sum();
+
+  for (cell, weight) in small_boys {
+    frequencies.entry(cell).or_default().0 += weight / small_total;
+  }
+  for (cell, weight) in big_boys {
+    frequencies.entry(cell).or_default().1 += weight / big_total;
+  }
+
+  frequencies
+    .values()
+    .map(|(s, b)| (s - b).abs())
+    .sum::<f64>()
+    / 2.0
+}
+
+/// Coarsens the covariates of `dataset` (within its strata) into cells, keeps
+/// the cells holding both groups and weights their records. Covariates
+/// without a bin count in `bins`, by name, use Sturges' rule. L1 imbalance is
+/// measured on a separate, finer Scott's-rule coarsening so that it is not zero
+/// by construction after matching.
+pub fn coarsened_exact_match(
+  dataset: &Dataset,
+  bins: &HashMap<String, usize>,
+  alternative: Alternative,
+) -> CemOutput {
+  let small_boys = &dataset.small[..];
+  let big_boys = &dataset.big[..];
+  let everyone = small_boys
+    .iter()
+    .chain(big_boys.iter())
+    .collect::<Vec<&Instance>>();
+  let n = everyone.len() as f64;
+
+  let sturges = (n.log2() + 1.0).ceil() as usize;
+  let cuts = dataset
+    .covariates
+    .iter()
+    .enumerate()
+    .map(|(k, c)| equal_width_bins(&everyone, k, *bins.get(c).unwrap_or(&sturges)))
+    .collect::<Vec<(f64, f64, usize)>>();
+
+  let scott_cuts = (0..dataset.covariates.len())
+    .map(|k| {
+      let values = everyone
+        .iter()
+        .map(|e| e.covariates[k])
+        .collect::<Vec<f64>>();
+      let (low, high) = values
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(l, h), x| {
+          (l.min(*x), h.max(*x))
+        });
+      let width = 3.5 * standard_deviation(&values[..], None) / n.cbrt();
+      let scott = if width > 0.0 {
+        ((high - low) / width).ceil().max(1.0) as usize
+      } else {
+        1
+      };
+      equal_width_bins(&everyone, k, scott)
+    })
+    .collect::<Vec<(f64, f64, usize)>>();
+
+  let small_cells = small_boys
+    .iter()
+    .map(|e| coarsened_cell(e, &cuts))
+    .collect::<Vec<String>>();
+  let big_cells = big_boys
+    .iter()
+    .map(|e| coarsened_cell(e, &cuts))
+    .collect::<Vec<String>>();
+
+  let mut counts: HashMap<&str, (usize, usize)> = HashMap::new();
+  for cell in small_cells.iter() {
+    counts.entry(cell).or_default().0 += 1;
+  }
+  for cell in big_cells.iter() {
+    counts.entry(cell).or_default().1 += 1;
+  }
+  let is_matched = |cell: &str| {
+    let (s, b) = counts[cell];
+    s > 0 && b > 0
+  };
+
+  let small_matched = small_cells.iter().filter(|c| is_matched(c)).count();
+  let big_matched = big_cells.iter().filter(|c| is_matched(c)).count();
+
+  // w = (m_small^s / m_big^s) * (m_big / m_small) for matched Big-Group records
+  let small_weights = small_cells
+    .iter()
+    .map(|c| if is_matched(c) { 1.0 } else { 0.0 })
+    .collect::<Vec<f64>>();
+  let big_weights = big_cells
+    .iter()
+    .map(|c| {
+      if is_matched(c) {
+        let (s, b) = counts[c.as_str()];
+        (s as f64 / b as f64) * (big_matched as f64 / small_matched as f64)
+      } else {
+        0.0
+      }
+    })
+    .collect::<Vec<f64>>();
+
+  // Records missing an outcome are left out of its comparison
+  let weighted_values = |records: &[Instance], weights: &[f64], k: usize| {
+    records
+      .iter()
+      .map(|e| e.outcomes[k])
+      .zip(weights.iter().cloned())
+      .filter(|(x, _)| !x.is_nan())
+      .collect::<Vec<(f64, f64)>>()
+  };
+  let weighted_mean = |xs: &[(f64, f64)]| {
+    xs.iter().map(|(x, w)| w * x).sum::<f64>() / xs.iter().map(|(_, w)| w).sum::<f64>()
+  };
+
+  let outcomes = dataset
+    .outcomes
+    .iter()
+    .enumerate()
+    .map(|(k, outcome)| {
+      let small_values = weighted_values(small_boys, &small_weights, k);
+      let big_values = weighted_values(big_boys, &big_weights, k);
+      let observations = small_values
+        .iter()
+        .chain(big_values.iter())
+        .filter(|(_, w)| *w > 0.0)
+        .count();
+      let small_mean = weighted_mean(&small_values);
+      let big_weighted_mean = weighted_mean(&big_values);
+      let t_test_result = weighted_t(small_values, big_values, alternative);
+
+      CemOutcome {
+        outcome: outcome.clone(),
+        observations,
+        small_mean,
+        big_weighted_mean,
+        difference: small_mean - big_weighted_mean,
+        t_pvalue: t_test_result.p,
+        t_tvalue: t_test_result.t,
+      }
+    })
+    .collect();
+
+  let scott_small = small_boys
+    .iter()
+    .map(|e| coarsened_cell(e, &scott_cuts))
+    .collect::<Vec<String>>();
+  let scott_big = big_boys
+    .iter()
+    .map(|e| coarsened_cell(e, &scott_cuts))
+    .collect::<Vec<String>>();
+  let unweighted = |cells: &[String]| cells.iter().map(|c| (c.clone(), 1.0)).collect::<Vec<_>>();
+  let weighted = |cells: &[String], weights: &[f64]| {
+    cells
+      .iter()
+      .cloned()
+      .zip(weights.iter().cloned())
+      .collect::<Vec<_>>()
+  };
+
+  CemOutput {
+    arm: dataset.arm.clone(),
+    strata: counts.len(),
+    matched_strata: counts.keys().filter(|c| is_matched(c)).count(),
+    small_matched,
+    small_unmatched: small_boys.len() - small_matched,
+    big_matched,
+    big_unmatched: big_boys.len() - big_matched,
+
+    outcomes,
+    alternative,
+
+    l1_before: l1_imbalance(&unweighted(&scott_small), &unweighted(&scott_big)),
+    l1_after: l1_imbalance(
+      &weighted(&scott_small, &small_weights),
+      &weighted(&scott_big, &big_weights),
+    ),
+  }
+}
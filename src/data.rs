@@ -0,0 +1,227 @@
+use crate::distance::{assign_coordinates, caliper_scale, Metric, PropensityModel};
+use crate::error::{Error, Result};
+use crate::missing::is_missing;
+use crate::schema::Schema;
+use std::fs::File;
+
+/// One student record, holding only the columns its schema gives a role.
+#[derive(Debug, Clone, Default)]
+pub struct Instance {
+  /// Identifies the record in the matched pairs.
+  pub id: String,
+  pub condition: String,
+
+  /// Values of the schema's covariates, in schema order; NaN when missing.
+  pub covariates: Vec<f64>,
+
+  /// Values of the schema's outcome columns, in schema order; NaN when
+  /// missing.
+  pub outcomes: Vec<f64>,
+
+  /// Values of the schema's strata columns, joined with "|". Records are only
+  /// matched within the same stratum.
+  pub stratum: String,
+
+  // Position of the record in the matching space. Matchers compare records
+  // by the Euclidean distance between these.
+  pub(crate) coords: Vec<f64>,
+}
+
+/// The records to match for one treatment arm: the Big-Group comparison pool
+/// and the Small-Group records to find partners for.
+#[derive(Debug, Clone)]
+pub struct Dataset {
+  /// Label of the arm, reported with every result.
+  pub arm: String,
+  pub small: Vec<Instance>,
+  pub big: Vec<Instance>,
+  /// Names of the records' covariates, in order; they are matched on.
+  pub covariates: Vec<String>,
+  /// Names of the records' outcomes, in order.
+  pub outcomes: Vec<String>,
+}
+
+impl Dataset {
+  /// Splits `records` into one dataset per treatment arm of `schema`, each
+  /// against the shared pool of control records. Without treatment labels,
+  /// every record that is not a control goes into a single arm, labelled with
+  /// its conditions joined by "+".
+  pub fn arms(records: &[Instance], schema: &Schema) -> Vec<Dataset> {
+    let big = records
+      .iter()
+      .filter(|e| e.condition == schema.control)
+      .cloned()
+      .collect::<Vec<Instance>>();
+    let arm = |label: String, small: Vec<Instance>| Dataset {
+      arm: label,
+      small,
+      big: big.clone(),
+      covariates: schema.covariates.clone(),
+      outcomes: schema.outcomes.clone(),
+    };
+
+    if schema.treatments.is_empty() {
+      let small = records
+        .iter()
+        .filter(|e| e.condition != schema.control)
+        .cloned()
+        .collect::<Vec<Instance>>();
+      let mut labels = small
+        .iter()
+        .map(|e| e.condition.as_str())
+        .collect::<Vec<&str>>();
+      labels.sort_unstable();
+      labels.dedup();
+
+      vec![arm(labels.join("+"), small)]
+    } else {
+      schema
+        .treatments
+        .iter()
+        .map(|label| {
+          let small = records
+            .iter()
+            .filter(|e| &e.condition == label)
+            .cloned()
+            .collect();
+          arm(label.clone(), small)
+        })
+        .collect()
+    }
+  }
+
+  /// Places every record in the matching space for `metric` on the
+  /// covariates. Must be called before matching. Returns the fitted model for
+  /// the propensity metrics. Fails if the model does not converge, or if the
+  /// covariates are constant or collinear so distances on them are undefined.
+  pub fn place(&mut self, metric: Metric) -> Result<Option<PropensityModel>> {
+    assign_coordinates(&mut self.small, &mut self.big, &self.covariates, metric).map_err(
+      |problem| {
+        let arm = self.arm.clone();
+        if metric.is_propensity() {
+          Error::Propensity { arm, problem }
+        } else {
+          Error::Covariates { arm, problem }
+        }
+      },
+    )
+  }
+
+  /// Fails unless the arm has the 2 treated records and 1 control that
+  /// matching and the paired tests need.
+  pub fn check_size(&self) -> Result<()> {
+    if self.small.len() >= 2 && !self.big.is_empty() {
+      Ok(())
+    } else {
+      Err(Error::TooFewRecords {
+        arm: self.arm.clone(),
+        treated: self.small.len(),
+        controls: self.big.len(),
+      })
+    }
+  }
+
+  // Fails unless every record has been placed in the same matching space.
+  pub(crate) fn check_placed(&self) -> Result<()> {
+    let mut records = self.small.iter().chain(self.big.iter());
+    let dimensions = records.clone().next().map_or(0, |e| e.coords.len());
+    let placed = dimensions > 0 && records.all(|e| e.coords.len() == dimensions);
+    if placed {
+      Ok(())
+    } else {
+      Err(Error::Unplaced {
+        arm: self.arm.clone(),
+      })
+    }
+  }
+
+  /// The distance that a caliper of one standard deviation stands for; NaN
+  /// when either group has fewer than two records.
+  pub fn caliper_scale(&self) -> f64 {
+    caliper_scale(&self.small, &self.big)
+  }
+}
+
+/// Reads every record of a CSV file with a header row, checking that the
+/// columns `schema` names exist and that its covariates and outcomes are
+/// numbers or missing. Without an ID column, each record's ID is its data row,
+/// counting from 1.
+pub fn read_csv_data(filename: &str, schema: &Schema) -> Result<Vec<Instance>> {
+  let file: File = File::open(filename).map_err(|e| Error::io(filename, e))?;
+  let mut reader = csv::Reader::from_reader(file);
+  let headers = reader
+    .headers()
+    .map_err(|e| Error::csv(filename, e))?
+    .clone();
+
+  // Where each column with a role is in a row
+  let find = |column: &String| {
+    headers
+      .iter()
+      .position(|header| header == column)
+      .ok_or_else(|| Error::MissingColumn {
+        file: filename.to_string(),
+        column: column.clone(),
+      })
+  };
+  let group = find(&schema.group)?;
+  let id = schema.id.as_ref().map(find).transpose()?;
+  let covariates = schema
+    .covariates
+    .iter()
+    .map(find)
+    .collect::<Result<Vec<usize>>>()?;
+  let outcomes = schema
+    .outcomes
+    .iter()
+    .map(find)
+    .collect::<Result<Vec<usize>>>()?;
+  let strata = schema
+    .strata
+    .iter()
+    .map(find)
+    .collect::<Result<Vec<usize>>>()?;
+
+  let mut data: Vec<Instance> = Vec::new();
+  for datum in reader.records() {
+    let record = datum.map_err(|e| Error::csv(filename, e))?;
+    let cell = |at: usize| record.get(at).unwrap_or("");
+    let number = |at: usize| {
+      let value = cell(at).trim();
+      if is_missing(value) {
+        return Ok(f64::NAN);
+      }
+      value.parse::<f64>().map_err(|_| Error::Value {
+        file: filename.to_string(),
+        line: record.position().map_or(0, |p| p.line()),
+        column: headers[at].to_string(),
+        value: value.to_string(),
+        problem: "not a number".to_string(),
+      })
+    };
+
+    data.push(Instance {
+      id: match id {
+        Some(at) => cell(at).to_string(),
+        None => (data.len() + 1).to_string(),
+      },
+      condition: cell(group).to_string(),
+      covariates: covariates
+        .iter()
+        .map(|&at| number(at))
+        .collect::<Result<Vec<f64>>>()?,
+      outcomes: outcomes
+        .iter()
+        .map(|&at| number(at))
+        .collect::<Result<Vec<f64>>>()?,
+      stratum: strata
+        .iter()
+        .map(|&at| cell(at))
+        .collect::<Vec<&str>>()
+        .join("|"),
+      coords: Vec::new(),
+    });
+  }
+
+  Ok(data)
+}
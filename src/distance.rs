@@ -0,0 +1,354 @@
+use crate::data::Instance;
+use crate::stats::sample_variance;
+use statistical::mean;
+
+/// How wide the caliper is: an absolute distance, or a multiple of the pooled
+/// standard deviation of the matching covariate (or propensity score).
+/// Distances on several covariates are already standardized, so there the two
+/// coincide.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaliperUnits {
+  Absolute,
+  Sd,
+}
+
+impl CaliperUnits {
+  pub fn from_arg(arg: &str) -> CaliperUnits {
+    match arg {
+      "sd" => CaliperUnits::Sd,
+      _ => CaliperUnits::Absolute,
+    }
+  }
+}
+
+/// How distances are measured. Mahalanobis and Euclidean only differ when
+/// matching on more than one covariate; a single covariate is matched on its
+/// absolute difference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+  /// Accounts for the pooled covariance between the covariates.
+  Mahalanobis,
+  /// Each covariate scaled by its pooled standard deviation, ignoring
+  /// correlations.
+  Euclidean,
+  /// Difference in the estimated probability of being in the Big-Group, from
+  /// a logistic regression on the covariates.
+  Propensity,
+  /// Difference in the log-odds of that probability.
+  PropensityLogit,
+}
+
+impl Metric {
+  pub fn from_arg(arg: &str) -> Metric {
+    match arg {
+      "euclidean" => Metric::Euclidean,
+      "propensity" => Metric::Propensity,
+      "propensity-logit" => Metric::PropensityLogit,
+      _ => Metric::Mahalanobis,
+    }
+  }
+
+  /// Whether records are placed by a fitted propensity model.
+  pub fn is_propensity(self) -> bool {
+    self == Metric::Propensity || self == Metric::PropensityLogit
+  }
+}
+
+/// A fitted logistic regression of Big-Group membership on the records'
+/// covariates, named by `columns`. `coefficients[0]` is the intercept,
+/// followed by one per covariate.
+#[derive(Debug, Clone)]
+pub struct PropensityModel {
+  pub columns: Vec<String>,
+  pub coefficients: Vec<f64>,
+}
+
+impl PropensityModel {
+  pub fn logit(&self, record: &Instance) -> f64 {
+    self.coefficients[0]
+      + record
+        .covariates
+        .iter()
+        .zip(self.coefficients[1..].iter())
+        .map(|(x, beta)| beta * x)
+        .sum::<f64>()
+  }
+
+  pub fn score(&self, record: &Instance) -> f64 {
+    1.0 / (1.0 + (-self.logit(record)).exp())
+  }
+}
+
+// Pooled within-group covariance matrix of the covariates named by `columns`:
+// ((n_s - 1) S_s + (n_b - 1) S_b) / (n_s + n_b - 2).
+fn pooled_covariance(
+  small_boys: &[Instance],
+  big_boys: &[Instance],
+  columns: &[String],
+) -> Vec<Vec<f64>> {
+  let p = columns.len();
+  let mut scatter = vec![vec![0.0; p]; p];
+
+  for group in [small_boys, big_boys].iter() {
+    let values = group
+      .iter()
+      .map(|e| e.covariates.clone())
+      .collect::<Vec<Vec<f64>>>();
+    let means = (0..p)
+      .map(|k| mean(&values.iter().map(|v| v[k]).collect::<Vec<f64>>()[..]))
+      .collect::<Vec<f64>>();
+
+    for v in values.iter() {
+      for r in 0..p {
+        for c in 0..p {
+          scatter[r][c] += (v[r] - means[r]) * (v[c] - means[c]);
+        }
+      }
+    }
+  }
+
+  let dof = (small_boys.len() + big_boys.len() - 2) as f64;
+  scatter
+    .iter()
+    .map(|row| row.iter().map(|x| x / dof).collect())
+    .collect()
+}
+
+// Lower-triangular L with L L^T = matrix, for a symmetric positive definite
+// matrix. Fails when a pivot is not positive, counting one that is lost to
+// rounding against its diagonal entry as 0: the matrix is singular.
+fn cholesky(matrix: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+  let p = matrix.len();
+  let mut lower = vec![vec![0.0; p]; p];
+
+  for r in 0..p {
+    for c in 0..=r {
+      let sum: f64 = (0..c).map(|k| lower[r][k] * lower[c][k]).sum();
+      if r == c {
+        let pivot = matrix[r][r] - sum;
+        if pivot.is_nan() || pivot <= matrix[r][r] * 1e-12 {
+          return None;
+        }
+        lower[r][c] = pivot.sqrt();
+      } else {
+        lower[r][c] = (matrix[r][c] - sum) / lower[c][c];
+      }
+    }
+  }
+
+  Some(lower)
+}
+
+// Solves matrix x = rhs for a symmetric positive definite matrix; None when
+// it is singular.
+fn solve_spd(matrix: &[Vec<f64>], rhs: &[f64]) -> Option<Vec<f64>> {
+  let p = rhs.len();
+  let lower = cholesky(matrix)?;
+
+  // Forward substitution solves L z = rhs, back substitution L^T x = z
+  let mut z = vec![0.0; p];
+  for r in 0..p {
+    let sum: f64 = (0..r).map(|k| lower[r][k] * z[k]).sum();
+    z[r] = (rhs[r] - sum) / lower[r][r];
+  }
+  let mut x = vec![0.0; p];
+  for r in (0..p).rev() {
+    let sum: f64 = (r + 1..p).map(|k| lower[k][r] * x[k]).sum();
+    x[r] = (z[r] - sum) / lower[r][r];
+  }
+
+  Some(x)
+}
+
+// Fits P(Big-Group | covariates) by maximum likelihood with Newton-Raphson
+// (iteratively reweighted least squares). Fails, rather than returning
+// infinite or NaN coefficients, when the covariates separate the groups and
+// there is no maximum to converge to.
+fn fit_propensity(
+  small_boys: &[Instance],
+  big_boys: &[Instance],
+  columns: &[String],
+) -> Result<PropensityModel, String> {
+  let design = small_boys
+    .iter()
+    .map(|e| (e, 0.0))
+    .chain(big_boys.iter().map(|e| (e, 1.0)))
+    .map(|(e, y)| {
+      let mut x = vec![1.0];
+      x.extend(e.covariates.iter());
+      (x, y)
+    })
+    .collect::<Vec<(Vec<f64>, f64)>>();
+
+  let p = columns.len() + 1;
+  let mut model = PropensityModel {
+    columns: columns.to_vec(),
+    coefficients: vec![0.0; p],
+  };
+
+  for _ in 0..100 {
+    let mut gradient = vec![0.0; p];
+    let mut hessian = vec![vec![0.0; p]; p];
+
+    for (x, y) in design.iter() {
+      let eta: f64 = x
+        .iter()
+        .zip(model.coefficients.iter())
+        .map(|(a, b)| a * b)
+        .sum();
+      let mu = 1.0 / (1.0 + (-eta).exp());
+      let weight = mu * (1.0 - mu);
+      for r in 0..p {
+        gradient[r] += x[r] * (y - mu);
+        for c in 0..p {
+          hessian[r][c] += weight * x[r] * x[c];
+        }
+      }
+    }
+
+    let step = solve_spd(&hessian, &gradient).ok_or_else(singular)?;
+    if step.iter().any(|delta| !delta.is_finite()) {
+      return Err(separated());
+    }
+    for (beta, delta) in model.coefficients.iter_mut().zip(step.iter()) {
+      *beta += delta;
+    }
+
+    if step.iter().all(|delta| delta.abs() < 1e-10) {
+      return Ok(model);
+    }
+  }
+
+  Err(separated())
+}
+
+// Why a propensity fit did not converge.
+fn separated() -> String {
+  "the fit does not converge; the matching covariates may separate the groups completely"
+    .to_string()
+}
+
+// Why the covariates cannot be standardized or whitened.
+fn singular() -> String {
+  "a matching covariate is constant, or a combination of the others".to_string()
+}
+
+// Sets `coords` on every record. The propensity metrics fit a logistic
+// regression and place each record at its score (or logit), returning the
+// model. Otherwise one covariate is used as is, and several are whitened with
+// the pooled covariance (Mahalanobis: coords = L^-1 x, where L L^T is the
+// covariance) or scaled by their pooled standard deviations (Euclidean), so
+// the Euclidean distance between coords is the chosen metric. Fails when the
+// propensity model cannot be fitted, or when several covariates have a
+// singular covariance (or one of them no variance, for Euclidean).
+pub(crate) fn assign_coordinates(
+  small_boys: &mut [Instance],
+  big_boys: &mut [Instance],
+  columns: &[String],
+  metric: Metric,
+) -> Result<Option<PropensityModel>, String> {
+  if metric.is_propensity() {
+    let model = fit_propensity(small_boys, big_boys, columns)?;
+    for record in small_boys.iter_mut().chain(big_boys.iter_mut()) {
+      record.coords = match metric {
+        Metric::PropensityLogit => vec![model.logit(record)],
+        _ => vec![model.score(record)],
+      };
+    }
+    return Ok(Some(model));
+  }
+
+  let p = columns.len();
+  let whiten: Box<dyn Fn(Vec<f64>) -> Vec<f64>> = if p == 1 {
+    Box::new(|x| x)
+  } else {
+    let covariance = pooled_covariance(small_boys, big_boys, columns);
+    match metric {
+      Metric::Euclidean => {
+        if !(0..p).all(|k| covariance[k][k] > 0.0) {
+          return Err(singular());
+        }
+        Box::new(move |x: Vec<f64>| (0..p).map(|k| x[k] / covariance[k][k].sqrt()).collect())
+      }
+      _ => {
+        let lower = cholesky(&covariance).ok_or_else(singular)?;
+        Box::new(move |x: Vec<f64>| {
+          // Forward substitution solves L z = x
+          let mut z = vec![0.0; p];
+          for r in 0..p {
+            let sum: f64 = (0..r).map(|k| lower[r][k] * z[k]).sum();
+            z[r] = (x[r] - sum) / lower[r][r];
+          }
+          z
+        })
+      }
+    }
+  };
+
+  for record in small_boys.iter_mut().chain(big_boys.iter_mut()) {
+    record.coords = whiten(record.covariates.clone());
+  }
+
+  Ok(None)
+}
+
+pub(crate) fn distance(a: &Instance, b: &Instance) -> f64 {
+  a.coords
+    .iter()
+    .zip(b.coords.iter())
+    .map(|(x, y)| (x - y).powi(2))
+    .sum::<f64>()
+    .sqrt()
+}
+
+// The pooled standard deviation of a single matching covariate or propensity
+// score, sqrt of the average of the two groups' variances, the usual scale
+// for a caliper expressed in standard deviations. Several covariates are
+// already standardized by `assign_coordinates`, so their scale is 1. NaN when
+// either group has fewer than two records.
+pub(crate) fn caliper_scale(small_boys: &[Instance], big_boys: &[Instance]) -> f64 {
+  if small_boys.first().map_or(1, |e| e.coords.len()) != 1 {
+    return 1.0;
+  }
+
+  let small_var = sample_variance(&small_boys.iter().map(|e| e.coords[0]).collect::<Vec<f64>>());
+  let big_var = sample_variance(&big_boys.iter().map(|e| e.coords[0]).collect::<Vec<f64>>());
+
+  ((small_var + big_var) / 2.0).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn records(x: f64, count: usize) -> Vec<Instance> {
+    (0..count)
+      .map(|_| Instance {
+        covariates: vec![x],
+        ..Instance::default()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn propensity_fit_recovers_the_log_odds_of_a_binary_covariate() {
+    // With one 0/1 covariate the fit is saturated: the intercept is the log
+    // odds of Big-Group at 0 (2 to 1) and the slope the log odds ratio
+    // against 1 (1 to 3)
+    let small_boys = [records(0.0, 1), records(1.0, 3)].concat();
+    let big_boys = [records(0.0, 2), records(1.0, 1)].concat();
+
+    let model = fit_propensity(&small_boys, &big_boys, &["x".to_string()]).unwrap();
+    assert!((model.coefficients[0] - 2f64.ln()).abs() < 1e-9);
+    assert!((model.coefficients[1] + 6f64.ln()).abs() < 1e-9);
+    assert!((model.score(&small_boys[0]) - 2.0 / 3.0).abs() < 1e-9);
+  }
+
+  #[test]
+  fn propensity_fit_fails_on_separated_groups() {
+    let small_boys = [records(0.0, 3), records(1.0, 2)].concat();
+    let big_boys = [records(2.0, 2), records(3.0, 3)].concat();
+
+    assert!(fit_propensity(&small_boys, &big_boys, &["x".to_string()]).is_err());
+  }
+}
@@ -0,0 +1,169 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong reading the input or writing the results.
+#[derive(Debug)]
+pub enum Error {
+  /// A file could not be opened, read or written.
+  Io { file: String, source: io::Error },
+  /// A CSV file is malformed, or could not be written.
+  Csv { file: String, source: csv::Error },
+  /// A JSON file could not be written.
+  Json {
+    file: String,
+    source: serde_json::Error,
+  },
+  /// The input has no column of this name.
+  MissingColumn { file: String, column: String },
+  /// No input record carries a group label the run needs.
+  MissingLabel {
+    file: String,
+    column: String,
+    label: String,
+  },
+  /// A cell of the input cannot be used. `line` counts the header as line 1.
+  Value {
+    file: String,
+    line: u64,
+    column: String,
+    value: String,
+    problem: String,
+  },
+  /// A column has no values in a group to impute the group's missing values
+  /// from.
+  NothingToImpute { column: String, group: String },
+  /// A line of a schema file cannot be understood.
+  Schema {
+    file: String,
+    line: usize,
+    problem: String,
+  },
+  /// The propensity model of an arm cannot be fitted.
+  Propensity { arm: String, problem: String },
+  /// The records of an arm cannot be placed in the matching space on their
+  /// covariates.
+  Covariates { arm: String, problem: String },
+  /// An arm has too few records to match and test: at least 2 treated records
+  /// and 1 control are needed.
+  TooFewRecords {
+    arm: String,
+    treated: usize,
+    controls: usize,
+  },
+  /// The records of an arm were matched before being placed in the matching
+  /// space.
+  Unplaced { arm: String },
+  /// A command-line value cannot be used.
+  Argument {
+    name: String,
+    value: String,
+    problem: String,
+  },
+}
+
+impl Error {
+  pub fn io(file: &str, source: io::Error) -> Error {
+    Error::Io {
+      file: file.to_string(),
+      source,
+    }
+  }
+
+  pub fn csv(file: &str, source: csv::Error) -> Error {
+    Error::Csv {
+      file: file.to_string(),
+      source,
+    }
+  }
+
+  pub fn json(file: &str, source: serde_json::Error) -> Error {
+    Error::Json {
+      file: file.to_string(),
+      source,
+    }
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Error::Io { file, source } => write!(f, "{}: {}", file, source),
+      Error::Csv { file, source } => write!(f, "{}: {}", file, source),
+      Error::Json { file, source } => write!(f, "{}: {}", file, source),
+      Error::MissingColumn { file, column } => write!(f, "{}: no column named `{}`", file, column),
+      Error::MissingLabel {
+        file,
+        column,
+        label,
+      } => write!(
+        f,
+        "{}: no record has `{}` in column `{}`",
+        file, label, column
+      ),
+      Error::Value {
+        file,
+        line,
+        column,
+        value,
+        problem,
+      } => write!(
+        f,
+        "{}, line {}, column `{}`: {} (found {:?})",
+        file, line, column, problem, value
+      ),
+      Error::NothingToImpute { column, group } => write!(
+        f,
+        "column `{}` has no values in group `{}` to impute its missing values from",
+        column, group
+      ),
+      Error::Schema {
+        file,
+        line,
+        problem,
+      } => write!(f, "{}, line {}: {}", file, line, problem),
+      Error::Propensity { arm, problem } => {
+        write!(f, "arm `{}`: propensity model: {}", arm, problem)
+      }
+      Error::Covariates { arm, problem } => {
+        write!(f, "arm `{}`: matching covariates: {}", arm, problem)
+      }
+      Error::TooFewRecords {
+        arm,
+        treated,
+        controls,
+      } => write!(
+        f,
+        "arm `{}`: needs at least 2 treated records and 1 control, but has {} and {}",
+        arm, treated, controls
+      ),
+      Error::Unplaced { arm } => write!(
+        f,
+        "arm `{}`: records must be placed in the matching space before matching",
+        arm
+      ),
+      Error::Argument {
+        name,
+        value,
+        problem,
+      } if value.is_empty() => write!(f, "--{}: {}", name, problem),
+      Error::Argument {
+        name,
+        value,
+        problem,
+      } => write!(f, "--{} {}: {}", name, value, problem),
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::Io { source, .. } => Some(source),
+      Error::Csv { source, .. } => Some(source),
+      Error::Json { source, .. } => Some(source),
+      _ => None,
+    }
+  }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
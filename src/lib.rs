@@ -0,0 +1,47 @@
+//! Matched-comparison sampling of Small-Group records against a Big-Group
+//! comparison pool.
+//!
+//! Read the records with [`read_csv_data`] through a [`Schema`] naming their
+//! columns, split them into one [`Dataset`] per treatment arm with
+//! [`Dataset::arms`], place each in the matching space with
+//! [`Dataset::place`], then [`run`] any number of seeded iterations under some
+//! [`Settings`] and condense them with [`Summary::new`]. Each iteration
+//! shuffles the Small-Group records, matches them with a [`Matcher`] and runs
+//! the paired [`Test`]s on each matched outcome; [`calibrate`] shows how
+//! often they come out significant without a true effect. A [`RecordWriter`] saves results
+//! as CSV, JSON or JSON Lines.
+
+mod balance;
+mod calibration;
+mod cem;
+mod data;
+mod distance;
+mod error;
+mod matching;
+mod missing;
+mod output;
+mod reuse;
+mod run;
+mod schema;
+mod stats;
+
+pub use crate::balance::Balance;
+pub use crate::calibration::{calibrate, NullProportion, NullSummary};
+pub use crate::cem::{coarsened_exact_match, CemOutcome, CemOutput};
+pub use crate::data::{read_csv_data, Dataset, Instance};
+pub use crate::distance::{CaliperUnits, Metric, PropensityModel};
+pub use crate::error::{Error, Result};
+pub use crate::matching::{Match, MatchOptions, Matcher, Matching};
+pub use crate::missing::{apply_missing_policy, is_missing, MissingPolicy, MissingReport};
+pub use crate::output::{Format, RecordWriter};
+pub use crate::reuse::{ControlReuse, ControlUse};
+pub use crate::run::{
+  iteration_seeds, run, run_iteration, BalanceSummary, MatchedPair, OutcomeStats, OutcomeSummary,
+  Output, Settings, StatisticSummary, StratumCount, StratumSummary, Summary,
+};
+pub use crate::schema::{split_columns, Schema};
+pub use crate::stats::{
+  paired_effect_size, paired_t, sample_stdev, sample_variance, sign_flip_permutation, sign_test,
+  weighted_t, wilcoxon_signed_rank, wilson_interval, Alternative, EffectSize, TTestResult, Test,
+  TestOutcome,
+};
@@ -1,74 +1,51 @@
 extern crate clap;
-extern crate serde;
-extern crate statistical;
-extern crate statrs;
 
+use adjei_sampling::{
+  apply_missing_policy, calibrate, coarsened_exact_match, iteration_seeds, read_csv_data, run,
+  sample_stdev, split_columns, Alternative, CaliperUnits, CemOutput, Dataset, Error, Format,
+  MatchOptions, Matcher, Metric, MissingPolicy, Output, PropensityModel, RecordWriter, Result,
+  Schema, Settings, Summary, Test,
+};
 use clap::{App, Arg};
-use rand::seq::SliceRandom;
-use rand::thread_rng;
-use serde::{Deserialize, Serialize};
-use statistical::{mean, standard_deviation};
-use statrs::distribution::{Continuous, StudentsT};
+use rand::{thread_rng, Rng};
+use serde::Serialize;
+use statistical::mean;
 use std::cmp::Ordering;
-use std::fs::File;
-
-#[derive(Debug, Deserialize, Clone)]
-struct Instance {
-  condition: String,
-  mid: f64,
-  pre: f64,
-  gain: f64,
-
-  #[serde(rename = "final")]
-  post: f64,
+use std::collections::HashMap;
+use std::fmt;
+use std::process;
+use std::str::FromStr;
+
+#[derive(Debug, Serialize)]
+struct SummaryLine {
+  arm: String,
+  statistic: String,
+  value: f64,
 }
 
-#[derive(Debug)]
-struct Match {
-  big: Instance,
-  small: Instance,
+#[derive(Debug, Serialize)]
+struct PropensityCoefficient {
+  arm: String,
+  term: String,
+  coefficient: f64,
 }
 
-#[derive(Debug, Serialize, Clone)]
-struct Output {
-  small_pre_mean: f64,
-  small_post_mean: f64,
-  small_mid_mean: f64,
-  small_gain_mean: f64,
-
-  big_pre_mean: f64,
-  big_post_mean: f64,
-  big_mid_mean: f64,
-  big_gain_mean: f64,
-
-  small_pre_stdev: f64,
-  small_post_stdev: f64,
-  small_mid_stdev: f64,
-  small_gain_stdev: f64,
-
-  big_pre_stdev: f64,
-  big_post_stdev: f64,
-  big_mid_stdev: f64,
-  big_gain_stdev: f64,
-
-  post_t_pvalue: f64,
-  post_t_tvalue: f64,
+#[derive(Debug, Serialize)]
+struct PropensityScore {
+  arm: String,
+  condition: String,
+  propensity: f64,
+  logit: f64,
 }
 
-fn read_csv_data(filename: &str) -> Box<Vec<Instance>> {
-  let file: File = File::open(filename).unwrap();
-  let mut reader = csv::Reader::from_reader(file);
-
-  let mut data: Vec<Instance> = Vec::new();
-  for datum in reader.deserialize() {
-    let instance: Instance = datum.unwrap();
-    data.push(instance);
+fn main() {
+  if let Err(error) = try_main() {
+    eprintln!("error: {}", error);
+    process::exit(1);
   }
-
-  return Box::new(data);
 }
 
-fn main() {
+fn try_main() -> Result<()> {
   // Declare cli args
   let opts = App::new("Data sample statistics tester")
     .version("0.1.0")
@@ -82,6 +59,295 @@ fn main() {
         .help("Number of iterations to run")
         .takes_value(true),
     )
+    .arg(
+      Arg::with_name("alternative")
+        .short("a")
+        .long("alternative")
+        .value_name("HYPOTHESIS")
+        .help("Alternative hypothesis of the paired tests")
+        .possible_values(&["two-sided", "small-greater", "small-less"])
+        .default_value("two-sided")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("test")
+        .long("test")
+        .value_name("TESTS")
+        .help("Comma-separated tests of the matched differences of every outcome")
+        .possible_values(&["t", "wilcoxon", "sign", "permutation"])
+        .default_value("t")
+        .use_delimiter(true)
+        .multiple(true)
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("alpha")
+        .long("alpha")
+        .value_name("LEVELS")
+        .help("Comma-separated p-value thresholds to report the proportion significant at")
+        .default_value("0.05")
+        .use_delimiter(true)
+        .multiple(true)
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("permutations")
+        .long("permutations")
+        .value_name("N")
+        .help("Number of sign flips drawn by the permutation test")
+        .default_value("1000")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("matcher")
+        .short("m")
+        .long("matcher")
+        .value_name("MATCHER")
+        .help("How Small-Group records are paired with Big-Group records")
+        .possible_values(&["greedy", "optimal", "cem"])
+        .default_value("greedy")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("caliper")
+        .short("c")
+        .long("caliper")
+        .value_name("WIDTH")
+        .help("Leave Small-Group records unmatched when no Big-Group record is within WIDTH")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("caliper-units")
+        .long("caliper-units")
+        .value_name("UNITS")
+        .help("Whether the caliper is an absolute distance or in pooled SDs of the covariate")
+        .possible_values(&["absolute", "sd"])
+        .default_value("absolute")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("ratio")
+        .short("k")
+        .long("ratio")
+        .value_name("K")
+        .help("Number of Big-Group controls to match to each Small-Group record")
+        .default_value("1")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("with-replacement")
+        .long("with-replacement")
+        .help("Allow a Big-Group record to be the control for several Small-Group records"),
+    )
+    .arg(
+      Arg::with_name("match-on")
+        .long("match-on")
+        .value_name("COLUMNS")
+        .help("Comma-separated covariate columns to match on [default: pre]")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("group-column")
+        .long("group-column")
+        .value_name("COLUMN")
+        .help("Column holding each record's condition [default: condition]")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("id-column")
+        .long("id-column")
+        .value_name("COLUMN")
+        .help("Column identifying each record in the pairs file [default: the data row]")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("outcomes")
+        .long("outcomes")
+        .value_name("COLUMNS")
+        .help("Comma-separated outcome columns to compare and test [default: mid,gain,final]")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("control")
+        .long("control")
+        .value_name("LABEL")
+        .help("Group label of the comparison pool [default: Big-Group]")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("treatment")
+        .long("treatment")
+        .value_name("LABELS")
+        .help(
+          "Comma-separated group labels, each matched against the controls as its own arm; \
+           every other label is pooled into one arm when omitted",
+        )
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("missing")
+        .long("missing")
+        .value_name("POLICY")
+        .help("What to do with records missing (blank or NA) a covariate or outcome")
+        .possible_values(&["drop-row", "drop-pairwise", "impute-mean"])
+        .default_value("drop-row")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("schema")
+        .long("schema")
+        .value_name("FILE")
+        .help(
+          "File of `role = columns` lines setting group, covariates, outcomes, strata and labels",
+        )
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("distance")
+        .long("distance")
+        .value_name("METRIC")
+        .help("Distance used to compare records on the matching covariates")
+        .possible_values(&["mahalanobis", "euclidean", "propensity", "propensity-logit"])
+        .default_value("mahalanobis")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("exact-on")
+        .long("exact-on")
+        .value_name("COLUMNS")
+        .help("Comma-separated categorical columns records must share to be matched")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("cem-bins")
+        .long("cem-bins")
+        .value_name("BINS")
+        .help(
+          "CEM bins per covariate: N for all, or column=N pairs; Sturges' rule when unspecified",
+        )
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("seed")
+        .short("s")
+        .long("seed")
+        .value_name("SEED")
+        .help("Master seed for the iteration seeds; random (and printed) when omitted")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("replay")
+        .long("replay")
+        .value_name("SEED")
+        .help("Run a single iteration with this iteration seed from the iterations file")
+        .conflicts_with("seed")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("format")
+        .short("f")
+        .long("format")
+        .value_name("FORMAT")
+        .help("Format of every output file")
+        .possible_values(&["csv", "json", "jsonl"])
+        .default_value("csv")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("iterations-out")
+        .short("o")
+        .long("iterations-out")
+        .value_name("PATH")
+        .help(
+          "Where to write every iteration's results, or every arm's CEM results \
+           [default: iterations.<format>, or cem.<format> with --matcher cem]",
+        )
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("summary-out")
+        .long("summary-out")
+        .value_name("PATH")
+        .help("Also write each arm's printed summary to PATH")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("reuse-out")
+        .long("reuse-out")
+        .value_name("PATH")
+        .help(
+          "Write how often each Big-Group record was selected to PATH, and summarize how much \
+           the controls of consecutive iterations overlap",
+        )
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("pairs-out")
+        .long("pairs-out")
+        .value_name("PATH")
+        .help("Write every matched pair of every iteration to PATH")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("balance-out")
+        .long("balance-out")
+        .value_name("PATH")
+        .help("Write the balance of every covariate in every iteration to PATH")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("strata-out")
+        .long("strata-out")
+        .value_name("PATH")
+        .help(
+          "Write the records matched in every exact-matching stratum of every iteration to PATH",
+        )
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("propensity-out")
+        .long("propensity-out")
+        .value_name("PATH")
+        .help("Write the coefficients of every arm's propensity model to PATH")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("scores-out")
+        .long("scores-out")
+        .value_name("PATH")
+        .help("Write every record's propensity score to PATH")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("calibrate")
+        .long("calibrate")
+        .value_name("N")
+        .help(
+          "Rerun every iteration on N replicates with the records shuffled between the groups, \
+           giving the null distribution of each proportion significant",
+        )
+        .conflicts_with("replay")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("calibration-out")
+        .long("calibration-out")
+        .value_name("PATH")
+        .help(
+          "Where to write every replicate's proportions significant \
+           [default: calibration.<format>]",
+        )
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("threads")
+        .short("t")
+        .long("threads")
+        .value_name("N")
+        .help("Number of threads to run iterations on; 0 uses every core")
+        .default_value("0")
+        .takes_value(true),
+    )
     .arg(
       Arg::with_name("input")
         .index(1)
@@ -90,408 +356,657 @@ fn main() {
     )
     .get_matches();
 
-  // Read in CSV data as specified by input parameter
-  let mut data = *read_csv_data(opts.value_of("input").unwrap());
+  // Map columns to roles: defaults, then the schema file, then the flags
+  let mut schema = match opts.value_of("schema") {
+    Some(filename) => Schema::from_file(filename)?,
+    None => Schema::default(),
+  };
+  if let Some(group) = opts.value_of("group-column") {
+    schema.group = group.to_string();
+  }
+  if let Some(id) = opts.value_of("id-column") {
+    schema.id = Some(id.to_string());
+  }
+  if let Some(columns) = opts.value_of("match-on") {
+    schema.covariates = split_columns(columns);
+  }
+  if let Some(columns) = opts.value_of("outcomes") {
+    schema.outcomes = split_columns(columns);
+  }
+  if let Some(columns) = opts.value_of("exact-on") {
+    schema.strata = split_columns(columns);
+  }
 
-  // Separate Big-Group and Small-Group-To-Match
-  let (all_big_boys, all_small_boys): (Vec<Instance>, Vec<Instance>) = data
-    .drain(..)
-    .partition(|element| element.condition == "Big-Group");
+  if let Some(label) = opts.value_of("control") {
+    schema.control = label.to_string();
+  }
+  if let Some(labels) = opts.value_of("treatment") {
+    schema.treatments = split_columns(labels);
+  }
 
-  let mut outputs: Vec<Output> = Vec::with_capacity(
-    opts
-      .value_of("iterations")
-      .unwrap()
-      .parse::<usize>()
-      .unwrap(),
-  );
-  let mut rng = thread_rng();
-
-  // Do as many iterations as specified in argument
-  for _ in 0..(opts.value_of("iterations").unwrap().parse::<i32>().unwrap()) {
-    // Clone our lists so we can pop off safely
-    let mut big_boys = all_big_boys.clone();
-    let mut small_boys = all_small_boys.clone();
-
-    let mut matches: Vec<Match> = Vec::new();
-
-    // Shuffle small_boys
-    small_boys.shuffle(&mut rng);
-
-    for record in small_boys.drain(..) {
-      // Sort big_boys by the absolute value of the difference between it's post
-      // and the current smallboy's record's post.
-      // Should be O(nlog(n)). Phew.
-      big_boys.sort_by(|a, b| {
-        (a.pre - record.pre)
-          .abs()
-          .partial_cmp(&(b.pre - record.pre).abs())
-          .unwrap_or(Ordering::Equal)
-          .reverse()
+  // Read in CSV data as specified by input parameter, and split it into the
+  // treatment arms, each against the shared control pool
+  let input = opts.value_of("input").unwrap();
+  let records = read_csv_data(input, &schema)?;
+  let (records, missing) = apply_missing_policy(
+    records,
+    &schema,
+    MissingPolicy::from_arg(opts.value_of("missing").unwrap()),
+  )?;
+  println!("missing_policy = {}", missing.policy);
+  println!("missing_values = {}", missing.missing_values);
+  println!(
+    "missing_incomplete_records = {}",
+    missing.incomplete_records
+  );
+  println!("missing_excluded_records = {}", missing.excluded_records);
+  println!("missing_imputed_values = {}", missing.imputed_values);
+
+  let mut arms = Dataset::arms(&records, &schema);
+  for label in std::iter::once(&schema.control).chain(schema.treatments.iter()) {
+    if !records.iter().any(|e| &e.condition == label) {
+      return Err(Error::MissingLabel {
+        file: input.to_string(),
+        column: schema.group.clone(),
+        label: label.clone(),
       });
+    }
+  }
+  for dataset in arms.iter() {
+    dataset.check_size()?;
+  }
+  let alternative = Alternative::from_arg(opts.value_of("alternative").unwrap());
+  let mut alphas: Vec<f64> = Vec::new();
+  for alpha in opts.values_of("alpha").unwrap() {
+    let level: f64 = parse_arg("alpha", alpha)?;
+    if !(level > 0.0 && level < 1.0) {
+      return Err(Error::Argument {
+        name: "alpha".to_string(),
+        value: alpha.to_string(),
+        problem: "must be between 0 and 1".to_string(),
+      });
+    }
+    if !alphas.contains(&level) {
+      alphas.push(level);
+    }
+  }
+  let mut tests: Vec<Test> = Vec::new();
+  for test in opts.values_of("test").unwrap().map(Test::from_arg) {
+    if !tests.contains(&test) {
+      tests.push(test);
+    }
+  }
+  let ratio: usize = parse_arg("ratio", opts.value_of("ratio").unwrap())?;
+  if ratio < 1 {
+    return Err(Error::Argument {
+      name: "ratio".to_string(),
+      value: opts.value_of("ratio").unwrap().to_string(),
+      problem: "must be at least 1".to_string(),
+    });
+  }
+  let permutations: usize = parse_arg("permutations", opts.value_of("permutations").unwrap())?;
+  if permutations < 1 {
+    return Err(Error::Argument {
+      name: "permutations".to_string(),
+      value: opts.value_of("permutations").unwrap().to_string(),
+      problem: "must be at least 1".to_string(),
+    });
+  }
+  // CEM weights records instead of pairing them, so it is not a Matcher
+  let cem = opts.value_of("matcher") == Some("cem");
+  let matcher = Matcher::from_arg(opts.value_of("matcher").unwrap());
+  let metric = Metric::from_arg(opts.value_of("distance").unwrap());
+
+  // Refuse output files this run would never write
+  let unwritten = |flags: &[&str], problem: &str| match flags.iter().find(|f| opts.is_present(f)) {
+    Some(flag) => Err(Error::Argument {
+      name: flag.to_string(),
+      value: String::new(),
+      problem: problem.to_string(),
+    }),
+    None => Ok(()),
+  };
+  if cem {
+    unwritten(
+      &[
+        "pairs-out",
+        "balance-out",
+        "strata-out",
+        "reuse-out",
+        "calibrate",
+      ],
+      "cannot be used with --matcher cem, which has no iterations",
+    )?;
+  }
+  if !metric.is_propensity() {
+    unwritten(
+      &["propensity-out", "scores-out"],
+      "needs a propensity --distance",
+    )?;
+  }
+  if schema.strata.is_empty() {
+    unwritten(&["strata-out"], "needs --exact-on")?;
+  }
 
-      // Because of the above sort, this pop returns the closest value in O(1).
-      let matched_record = big_boys.pop().unwrap();
+  // Every arm's results go to the same files
+  let format = Format::from_arg(opts.value_of("format").unwrap());
+  let mut summary_writer = create_writer(opts.value_of("summary-out"), format)?;
+  let mut propensity_writers = if metric.is_propensity() {
+    (
+      create_writer(opts.value_of("propensity-out"), format)?,
+      create_writer(opts.value_of("scores-out"), format)?,
+    )
+  } else {
+    (None, None)
+  };
+
+  if cem {
+    let mut bins: HashMap<String, usize> = HashMap::new();
+    for item in opts.value_of("cem-bins").unwrap_or("").split(',') {
+      let item = item.trim();
+      if item.is_empty() {
+        continue;
+      }
+      match item.find('=') {
+        Some(at) => bins.insert(item[..at].trim().to_string(), parse_bins(&item[at + 1..])?),
+        None => {
+          let count = parse_bins(item)?;
+          for column in schema.covariates.iter() {
+            bins.entry(column.clone()).or_insert(count);
+          }
+          None
+        }
+      };
+    }
 
-      matches.push(Match {
-        small: record,
-        big: matched_record,
-      });
+    let cem_out = match opts.value_of("iterations-out") {
+      Some(path) => path.to_string(),
+      None => format!("cem.{}", format.extension()),
+    };
+    let mut writer = RecordWriter::create(&cem_out, format)?;
+    for dataset in arms.iter_mut() {
+      println!("arm = {}", dataset.arm);
+      if let Some(model) = dataset.place(metric)? {
+        report_propensity(&model, dataset, &mut propensity_writers)?;
+      }
+
+      let output = coarsened_exact_match(dataset, &bins, alternative);
+      writer.write_row(&output, output.headers(), output.record())?;
+
+      let lines = cem_lines(&output);
+      for (statistic, value) in lines.iter() {
+        println!("{} = {}", statistic, value);
+      }
+      println!("alternative = {}", opts.value_of("alternative").unwrap());
+
+      // As for the iterations: CSV gets the printed lines, JSON the result
+      if let Some(writer) = summary_writer.as_mut() {
+        if format == Format::Csv {
+          for (statistic, value) in lines {
+            writer.write(&SummaryLine {
+              arm: output.arm.clone(),
+              statistic,
+              value,
+            })?;
+          }
+        } else {
+          writer.write(&output)?;
+        }
+      }
+    }
+    writer.finish()?;
+    if let Some(writer) = summary_writer {
+      writer.finish()?;
     }
+    return finish_propensity(propensity_writers);
+  }
 
-    let t_test_result = paired_t(
-      matches.iter().map(|e| e.small.post).collect::<Vec<f64>>(),
-      matches.iter().map(|e| e.big.post).collect::<Vec<f64>>(),
-    );
-
-    let output = Output {
-      small_pre_mean: mean(&matches.iter().map(|e| e.small.pre).collect::<Vec<f64>>()[..]),
-      small_post_mean: mean(&matches.iter().map(|e| e.small.post).collect::<Vec<f64>>()[..]),
-      small_mid_mean: mean(&matches.iter().map(|e| e.small.mid).collect::<Vec<f64>>()[..]),
-      small_gain_mean: mean(&matches.iter().map(|e| e.small.gain).collect::<Vec<f64>>()[..]),
-
-      big_pre_mean: mean(&matches.iter().map(|e| e.big.pre).collect::<Vec<f64>>()[..]),
-      big_post_mean: mean(&matches.iter().map(|e| e.big.post).collect::<Vec<f64>>()[..]),
-      big_mid_mean: mean(&matches.iter().map(|e| e.big.mid).collect::<Vec<f64>>()[..]),
-      big_gain_mean: mean(&matches.iter().map(|e| e.big.gain).collect::<Vec<f64>>()[..]),
-
-      small_pre_stdev: standard_deviation(
-        &matches.iter().map(|e| e.small.pre).collect::<Vec<f64>>()[..],
-        None,
-      ),
-      small_post_stdev: standard_deviation(
-        &matches.iter().map(|e| e.small.post).collect::<Vec<f64>>()[..],
-        None,
-      ),
-      small_mid_stdev: standard_deviation(
-        &matches.iter().map(|e| e.small.mid).collect::<Vec<f64>>()[..],
-        None,
-      ),
-      small_gain_stdev: standard_deviation(
-        &matches.iter().map(|e| e.small.gain).collect::<Vec<f64>>()[..],
-        None,
-      ),
+  // Every iteration gets its own seed, drawn in order from the master seed, so
+  // any one of them can be replayed on its own. Every arm uses the same seeds.
+  // The seeds of the calibration replicates follow those of the iterations.
+  let master_seed = match opts.value_of("seed") {
+    Some(seed) => parse_arg("seed", seed)?,
+    None => thread_rng().gen(),
+  };
+  let replicates: usize = match opts.value_of("calibrate") {
+    Some(replicates) => parse_arg("calibrate", replicates)?,
+    None => 0,
+  };
+  let mut seeds: Vec<u64> = match opts.value_of("replay") {
+    Some(seed) => vec![parse_arg("replay", seed)?],
+    None => {
+      let value = opts.value_of("iterations").ok_or_else(|| Error::Argument {
+        name: "iterations".to_string(),
+        value: String::new(),
+        problem: "required unless replaying a seed".to_string(),
+      })?;
+      let iterations: usize = parse_arg("iterations", value)?;
+      if iterations < 2 {
+        return Err(Error::Argument {
+          name: "iterations".to_string(),
+          value: value.to_string(),
+          problem: "must be at least 2 to summarize across; rerun a single iteration with --replay"
+            .to_string(),
+        });
+      }
+      println!("seed = {}", master_seed);
+      iteration_seeds(master_seed, iterations + replicates)
+    }
+  };
+  let replicate_seeds = seeds.split_off(seeds.len() - replicates);
+
+  let pool = rayon::ThreadPoolBuilder::new()
+    .num_threads(parse_arg("threads", opts.value_of("threads").unwrap())?)
+    .build()
+    .map_err(|e| Error::Argument {
+      name: "threads".to_string(),
+      value: opts.value_of("threads").unwrap().to_string(),
+      problem: e.to_string(),
+    })?;
+
+  let iterations_out = match opts.value_of("iterations-out") {
+    Some(path) => path.to_string(),
+    None => format!("iterations.{}", format.extension()),
+  };
+  let mut iterations_writer = RecordWriter::create(&iterations_out, format)?;
+  let mut reuse_writer = create_writer(opts.value_of("reuse-out"), format)?;
+  let mut calibration_writer = if replicates > 0 {
+    Some(RecordWriter::create(
+      opts
+        .value_of("calibration-out")
+        .map(String::from)
+        .unwrap_or_else(|| format!("calibration.{}", format.extension()))
+        .as_str(),
+      format,
+    )?)
+  } else {
+    None
+  };
+  let mut pairs_writer = create_writer(opts.value_of("pairs-out"), format)?;
+  let mut balance_writer = create_writer(opts.value_of("balance-out"), format)?;
+  let mut strata_writer = if schema.strata.is_empty() {
+    None
+  } else {
+    create_writer(opts.value_of("strata-out"), format)?
+  };
+
+  for dataset in arms.iter_mut() {
+    println!("arm = {}", dataset.arm);
+
+    // Place every record in the matching space
+    if let Some(model) = dataset.place(metric)? {
+      report_propensity(&model, dataset, &mut propensity_writers)?;
+    }
 
-      big_pre_stdev: standard_deviation(
-        &matches.iter().map(|e| e.big.pre).collect::<Vec<f64>>()[..],
-        None,
-      ),
-      big_post_stdev: standard_deviation(
-        &matches.iter().map(|e| e.big.post).collect::<Vec<f64>>()[..],
-        None,
-      ),
-      big_mid_stdev: standard_deviation(
-        &matches.iter().map(|e| e.big.mid).collect::<Vec<f64>>()[..],
-        None,
-      ),
-      big_gain_stdev: standard_deviation(
-        &matches.iter().map(|e| e.big.gain).collect::<Vec<f64>>()[..],
-        None,
-      ),
+    // Resolve the caliper to an absolute distance once, up front
+    let caliper = match opts.value_of("caliper") {
+      Some(width) => {
+        let width: f64 = parse_arg("caliper", width)?;
+        Some(
+          match CaliperUnits::from_arg(opts.value_of("caliper-units").unwrap()) {
+            CaliperUnits::Absolute => width,
+            CaliperUnits::Sd => {
+              let scale = dataset.caliper_scale();
+              if !(scale.is_finite() && scale > 0.0) {
+                return Err(Error::Argument {
+                  name: "caliper-units".to_string(),
+                  value: "sd".to_string(),
+                  problem: format!(
+                    "arm `{}`: the pooled standard deviation is {}, so it cannot scale the caliper",
+                    dataset.arm, scale
+                  ),
+                });
+              }
+              width * scale
+            }
+          },
+        )
+      }
+      None => None,
+    };
 
-      post_t_pvalue: t_test_result.p,
-      post_t_tvalue: t_test_result.t,
+    let settings = Settings {
+      matcher,
+      options: MatchOptions {
+        caliper,
+        ratio,
+        with_replacement: opts.is_present("with-replacement"),
+      },
+      alternative,
+      tests: tests.clone(),
+      permutations,
+      pairs: pairs_writer.is_some(),
+      reuse: reuse_writer.is_some(),
     };
 
-    outputs.push(output);
+    // Do as many iterations as specified in argument
+    let outputs: Vec<Output> = pool.install(|| run(dataset, &settings, &seeds))?;
+
+    // Save the iterations
+    for output in outputs.iter() {
+      iterations_writer.write_row(output, output.headers(), output.record())?;
+    }
+
+    // Save the matched pairs
+    if let Some(writer) = pairs_writer.as_mut() {
+      for pair in outputs.iter().flat_map(|e| e.pairs.iter()) {
+        writer.write(pair)?;
+      }
+    }
+
+    // Save the covariate balance
+    if let Some(writer) = balance_writer.as_mut() {
+      for balance in outputs.iter().flat_map(|e| e.balance.iter()) {
+        writer.write(balance)?;
+      }
+    }
+
+    // Save the per-stratum counts when matching exactly
+    if let Some(writer) = strata_writer.as_mut() {
+      for count in outputs.iter().flat_map(|e| e.strata.iter()) {
+        writer.write(count)?;
+      }
+    }
+
+    // A replayed iteration has nothing to summarize across
+    if let Some(mut summary) = Summary::new(dataset, &outputs, &alphas) {
+      // Rerun everything without a true effect
+      if let Some(writer) = calibration_writer.as_mut() {
+        let null = pool.install(|| {
+          calibrate(
+            dataset,
+            &settings,
+            metric,
+            &seeds,
+            &replicate_seeds,
+            &alphas,
+          )
+        })?;
+        for proportion in null.iter() {
+          writer.write(proportion)?;
+        }
+        summary.add_null(&null);
+      }
+
+      // Save how often each control was selected
+      if let (Some(writer), Some(reuse)) = (reuse_writer.as_mut(), summary.control_reuse.as_ref()) {
+        for control in reuse.controls.iter() {
+          writer.write(control)?;
+        }
+      }
+
+      let lines = summary_lines(&summary, !schema.strata.is_empty());
+      for (statistic, value) in lines.iter() {
+        println!("{} = {}", statistic, value);
+      }
+      println!("alternative = {}", opts.value_of("alternative").unwrap());
+
+      // CSV gets the printed lines, one row each; JSON the whole summary
+      if let Some(writer) = summary_writer.as_mut() {
+        if format == Format::Csv {
+          for (statistic, value) in lines {
+            writer.write(&SummaryLine {
+              arm: summary.arm.clone(),
+              statistic,
+              value,
+            })?;
+          }
+        } else {
+          writer.write(&summary)?;
+        }
+      }
+    }
   }
 
-  // Save the iterations
-  let mut writer = csv::Writer::from_path("iterations.csv").unwrap();
-  for record in outputs.clone() {
-    writer.serialize(record).unwrap();
+  iterations_writer.finish()?;
+  for writer in vec![
+    summary_writer,
+    reuse_writer,
+    calibration_writer,
+    pairs_writer,
+    balance_writer,
+    strata_writer,
+  ]
+  .into_iter()
+  .flatten()
+  {
+    writer.finish()?;
   }
 
-  let small_pre_mean_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.small_pre_mean)
-      .collect::<Vec<f64>>()[..],
-  );
-  let big_pre_mean_mean = mean(&outputs.iter().map(|e| e.big_pre_mean).collect::<Vec<f64>>()[..]);
-  let small_post_mean_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.small_post_mean)
-      .collect::<Vec<f64>>()[..],
-  );
-  let big_post_mean_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.big_post_mean)
-      .collect::<Vec<f64>>()[..],
-  );
-  let small_mid_mean_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.small_mid_mean)
-      .collect::<Vec<f64>>()[..],
-  );
-  let big_mid_mean_mean = mean(&outputs.iter().map(|e| e.big_mid_mean).collect::<Vec<f64>>()[..]);
-  let small_pre_stdev_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.small_pre_stdev)
-      .collect::<Vec<f64>>()[..],
-  );
-  let big_pre_stdev_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.big_pre_stdev)
-      .collect::<Vec<f64>>()[..],
-  );
-  let small_post_stdev_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.small_post_stdev)
-      .collect::<Vec<f64>>()[..],
-  );
-  let big_post_stdev_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.big_post_stdev)
-      .collect::<Vec<f64>>()[..],
-  );
-  let small_mid_stdev_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.small_mid_stdev)
-      .collect::<Vec<f64>>()[..],
-  );
-  let big_mid_stdev_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.big_mid_stdev)
-      .collect::<Vec<f64>>()[..],
-  );
+  finish_propensity(propensity_writers)
+}
 
-  let post_t_pvalue_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.post_t_pvalue)
-      .collect::<Vec<f64>>()[..],
-  );
-  let post_t_tvalue_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.post_t_tvalue)
-      .collect::<Vec<f64>>()[..],
-  );
+// The across-iteration summary of one arm, as the `name = value` lines it is
+// printed as.
+fn summary_lines(summary: &Summary, exact: bool) -> Vec<(String, f64)> {
+  let mut lines = vec![("unmatched_mean".to_string(), summary.unmatched_mean)];
+  for balance in summary.balance.iter() {
+    let covariate = &balance.covariate;
+    for (name, value) in [
+      ("smd_before", balance.smd_before),
+      ("smd_after_mean", balance.smd_after_mean),
+      ("smd_after_stdev", balance.smd_after_stdev),
+      ("smd_after_max_abs", balance.smd_after_max_abs),
+      ("variance_ratio_before", balance.variance_ratio_before),
+      (
+        "variance_ratio_after_mean",
+        balance.variance_ratio_after_mean,
+      ),
+      (
+        "variance_ratio_after_stdev",
+        balance.variance_ratio_after_stdev,
+      ),
+    ]
+    .iter()
+    {
+      lines.push((format!("balance[{}]_{}", covariate, name), *value));
+    }
+  }
+  if exact {
+    for stratum in summary.strata.iter() {
+      lines.push((
+        format!("stratum[{}]_matched_mean", stratum.stratum),
+        stratum.matched_mean,
+      ));
+      lines.push((
+        format!("stratum[{}]_unmatched_mean", stratum.stratum),
+        stratum.unmatched_mean,
+      ));
+    }
+  }
+  if let Some(reuse) = summary.control_reuse.as_ref() {
+    for (name, value) in [
+      ("pool", reuse.pool as f64),
+      ("ever_selected", reuse.ever_selected as f64),
+      ("always_selected", reuse.always_selected as f64),
+      ("overlap_mean", reuse.overlap_mean),
+      ("overlap_stdev", reuse.overlap_stdev),
+      ("overlap_min", reuse.overlap_min),
+      ("overlap_max", reuse.overlap_max),
+    ]
+    .iter()
+    {
+      lines.push((format!("control_reuse_{}", name), *value));
+    }
+  }
+  for outcome in summary.outcomes.iter() {
+    for statistic in outcome.statistics.iter() {
+      lines.push((
+        format!("{}_{}_mean", outcome.outcome, statistic.name),
+        statistic.mean,
+      ));
+      lines.push((
+        format!("{}_{}_stdev", outcome.outcome, statistic.name),
+        statistic.stdev,
+      ));
+      // Only worth a line when some iterations had no value
+      if statistic.count < summary.iterations {
+        lines.push((
+          format!("{}_{}_count", outcome.outcome, statistic.name),
+          statistic.count as f64,
+        ));
+      }
+    }
+    for significance in outcome.significance.iter() {
+      let prefix = format!(
+        "{}_{}_proportion_significant[{}]",
+        outcome.outcome, significance.test, significance.alpha
+      );
+      lines.push((prefix.clone(), significance.proportion_significant));
+      lines.push((format!("{}_ci_lower", prefix), significance.ci_lower));
+      lines.push((format!("{}_ci_upper", prefix), significance.ci_upper));
+      if significance.tested < summary.iterations {
+        lines.push((format!("{}_tested", prefix), significance.tested as f64));
+      }
+      if let Some(null) = significance.null.as_ref() {
+        for (name, value) in [
+          ("null_replicates", null.replicates as f64),
+          ("null_mean", null.mean),
+          ("null_stdev", null.stdev),
+          ("null_q95", null.q95),
+          ("null_pvalue", null.pvalue),
+        ]
+        .iter()
+        {
+          lines.push((format!("{}_{}", prefix, name), *value));
+        }
+      }
+    }
+  }
 
-  let small_pre_mean_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.small_pre_mean)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
-  let big_pre_mean_stdev = standard_deviation(
-    &outputs.iter().map(|e| e.big_pre_mean).collect::<Vec<f64>>()[..],
-    None,
-  );
-  let small_post_mean_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.small_post_mean)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
-  let big_post_mean_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.big_post_mean)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
-  let small_mid_mean_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.small_mid_mean)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
-  let big_mid_mean_stdev = standard_deviation(
-    &outputs.iter().map(|e| e.big_mid_mean).collect::<Vec<f64>>()[..],
-    None,
-  );
+  lines
+}
 
-  let small_pre_stdev_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.small_pre_stdev)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
-  let big_pre_stdev_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.big_pre_stdev)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
-  let small_post_stdev_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.small_post_stdev)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
-  let big_post_stdev_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.big_post_stdev)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
-  let small_mid_stdev_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.small_mid_stdev)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
-  let big_mid_stdev_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.big_mid_stdev)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
+// The model and scores writers of the propensity metrics, each only when asked
+// for.
+type PropensityWriters = (Option<RecordWriter>, Option<RecordWriter>);
+
+// The result of CEM on one arm, as the `name = value` lines it is printed as.
+fn cem_lines(output: &CemOutput) -> Vec<(String, f64)> {
+  let mut lines = vec![
+    ("strata".to_string(), output.strata as f64),
+    ("matched_strata".to_string(), output.matched_strata as f64),
+    ("small_matched".to_string(), output.small_matched as f64),
+    ("small_unmatched".to_string(), output.small_unmatched as f64),
+    ("big_matched".to_string(), output.big_matched as f64),
+    ("big_unmatched".to_string(), output.big_unmatched as f64),
+  ];
+  for outcome in output.outcomes.iter() {
+    for (name, value) in outcome.statistics() {
+      lines.push((format!("{}_{}", outcome.outcome, name), value));
+    }
+  }
+  lines.push(("l1_before".to_string(), output.l1_before));
+  lines.push(("l1_after".to_string(), output.l1_after));
 
-  let post_t_pvalue_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.post_t_pvalue)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
-  let post_t_tvalue_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.post_t_tvalue)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
+  lines
+}
 
-  let small_gain_mean_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.small_gain_mean)
-      .collect::<Vec<f64>>()[..],
-  );
-  let big_gain_mean_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.big_gain_mean)
-      .collect::<Vec<f64>>()[..],
-  );
-  let small_gain_mean_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.small_gain_mean)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
-  let big_gain_mean_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.big_gain_mean)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
+// Prints the fitted coefficients of one arm and each group's score
+// distribution, and writes the coefficients and every record's score to
+// `writers`.
+fn report_propensity(
+  model: &PropensityModel,
+  dataset: &Dataset,
+  writers: &mut PropensityWriters,
+) -> Result<()> {
+  let (model_writer, scores_writer) = writers;
+  let terms = std::iter::once("(intercept)".to_string()).chain(model.columns.iter().cloned());
+  for (term, coefficient) in terms.zip(model.coefficients.iter()) {
+    println!("propensity_coefficient[{}] = {}", term, coefficient);
+    if let Some(writer) = model_writer.as_mut() {
+      writer.write(&PropensityCoefficient {
+        arm: dataset.arm.clone(),
+        term,
+        coefficient: *coefficient,
+      })?;
+    }
+  }
 
-  let small_gain_stdev_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.small_gain_stdev)
-      .collect::<Vec<f64>>()[..],
-  );
-  let big_gain_stdev_mean = mean(
-    &outputs
-      .iter()
-      .map(|e| e.big_gain_stdev)
-      .collect::<Vec<f64>>()[..],
-  );
-  let small_gain_stdev_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.small_gain_stdev)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
-  let big_gain_stdev_stdev = standard_deviation(
-    &outputs
-      .iter()
-      .map(|e| e.big_gain_stdev)
-      .collect::<Vec<f64>>()[..],
-    None,
-  );
+  if let Some(writer) = scores_writer.as_mut() {
+    for record in dataset.small.iter().chain(dataset.big.iter()) {
+      writer.write(&PropensityScore {
+        arm: dataset.arm.clone(),
+        condition: record.condition.clone(),
+        propensity: model.score(record),
+        logit: model.logit(record),
+      })?;
+    }
+  }
+
+  for (group, records) in [("small", &dataset.small), ("big", &dataset.big)].iter() {
+    let mut scores = records.iter().map(|e| model.score(e)).collect::<Vec<f64>>();
+    scores.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let (min, median, max) = order_statistics(&scores);
+    let mean = if scores.is_empty() {
+      f64::NAN
+    } else {
+      mean(&scores[..])
+    };
+    println!("{}_propensity_mean = {}", group, mean);
+    println!("{}_propensity_stdev = {}", group, sample_stdev(&scores));
+    println!("{}_propensity_min = {}", group, min);
+    println!("{}_propensity_median = {}", group, median);
+    println!("{}_propensity_max = {}", group, max);
+  }
+
+  Ok(())
+}
 
-  let proportion_significant =
-    outputs.iter().filter(|e| e.post_t_pvalue < 0.05).count() as f64 / outputs.len() as f64;
-
-  println!("small_pre_mean_mean = {}", small_pre_mean_mean);
-  println!("big_pre_mean_mean = {}", big_pre_mean_mean);
-  println!("small_post_mean_mean = {}", small_post_mean_mean);
-  println!("big_post_mean_mean = {}", big_post_mean_mean);
-  println!("small_mid_mean_mean = {}", small_mid_mean_mean);
-  println!("big_mid_mean_mean = {}", big_mid_mean_mean);
-  println!("small_pre_stdev_mean = {}", small_pre_stdev_mean);
-  println!("big_pre_stdev_mean = {}", big_pre_stdev_mean);
-  println!("small_post_stdev_mean = {}", small_post_stdev_mean);
-  println!("big_post_stdev_mean = {}", big_post_stdev_mean);
-  println!("small_mid_stdev_mean = {}", small_mid_stdev_mean);
-  println!("big_mid_stdev_mean = {}", big_mid_stdev_mean);
-  println!("post_t_pvalue_mean = {}", post_t_pvalue_mean);
-  println!("post_t_tvalue_mean = {}", post_t_tvalue_mean);
-  println!("small_pre_mean_stdev = {}", small_pre_mean_stdev);
-  println!("big_pre_mean_stdev = {}", big_pre_mean_stdev);
-  println!("small_post_mean_stdev = {}", small_post_mean_stdev);
-  println!("big_post_mean_stdev = {}", big_post_mean_stdev);
-  println!("small_mid_mean_stdev = {}", small_mid_mean_stdev);
-  println!("big_mid_mean_stdev = {}", big_mid_mean_stdev);
-  println!("small_pre_stdev_stdev = {}", small_pre_stdev_stdev);
-  println!("big_pre_stdev_stdev = {}", big_pre_stdev_stdev);
-  println!("small_post_stdev_stdev = {}", small_post_stdev_stdev);
-  println!("big_post_stdev_stdev = {}", big_post_stdev_stdev);
-  println!("small_mid_stdev_stdev = {}", small_mid_stdev_stdev);
-  println!("big_mid_stdev_stdev = {}", big_mid_stdev_stdev);
-  println!("small_gain_mean_mean = {}", small_gain_mean_mean);
-  println!("small_gain_mean_stdev = {}", small_gain_mean_stdev);
-  println!("big_gain_mean_mean = {}", big_gain_mean_mean);
-  println!("big_gain_mean_stdev = {}", big_gain_mean_stdev);
-  println!("small_gain_stdev_mean = {}", small_gain_stdev_mean);
-  println!("small_gain_stdev_stdev = {}", small_gain_stdev_stdev);
-  println!("big_gain_stdev_mean = {}", big_gain_stdev_mean);
-  println!("big_gain_stdev_stdev = {}", big_gain_stdev_stdev);
-  println!("post_t_pvalue_mean = {}", post_t_pvalue_mean);
-  println!("post_t_pvalue_stdev = {}", post_t_pvalue_stdev);
-  println!("post_t_tvalue_mean = {}", post_t_tvalue_mean);
-  println!("post_t_tvalue_stdev = {}", post_t_tvalue_stdev);
-  println!("proportion_significant = {}", proportion_significant);
+// The minimum, median and maximum of sorted `values`; NaN when there are none.
+fn order_statistics(values: &[f64]) -> (f64, f64, f64) {
+  let n = values.len();
+  if n == 0 {
+    return (f64::NAN, f64::NAN, f64::NAN);
+  }
+  let median = if n % 2 == 1 {
+    values[n / 2]
+  } else {
+    (values[n / 2 - 1] + values[n / 2]) / 2.0
+  };
+
+  (values[0], median, values[n - 1])
 }
 
-struct TTestResult {
-  p: f64,
-  t: f64,
+// Creates (or truncates) an optional output file, when its path is given.
+fn create_writer(path: Option<&str>, format: Format) -> Result<Option<RecordWriter>> {
+  path
+    .map(|path| RecordWriter::create(path, format))
+    .transpose()
 }
-fn paired_t(a: Vec<f64>, b: Vec<f64>) -> TTestResult {
-  let n = a.len();
 
-  let d = a
-    .iter()
-    .zip(b.iter())
-    .map(|(a, b)| a - b)
-    .collect::<Vec<f64>>();
-  let dbar = mean(&d[..]);
-  let sd = standard_deviation(&d[..], None);
+// Finishes whichever propensity files were written.
+fn finish_propensity(writers: PropensityWriters) -> Result<()> {
+  let (model_writer, scores_writer) = writers;
+  for writer in model_writer.into_iter().chain(scores_writer) {
+    writer.finish()?;
+  }
 
-  let se_dbar = sd / (n as f64).sqrt();
+  Ok(())
+}
 
-  let t = dbar / se_dbar;
+// Parses one bin count of --cem-bins, which must be at least 1.
+fn parse_bins(value: &str) -> Result<usize> {
+  let bins = parse_arg("cem-bins", value)?;
+  if bins < 1 {
+    return Err(Error::Argument {
+      name: "cem-bins".to_string(),
+      value: value.to_string(),
+      problem: "must be at least 1".to_string(),
+    });
+  }
 
-  let t_tester = StudentsT::new(0.0, 1.0, (n - 1) as f64).unwrap();
-  let p = t_tester.pdf(t);
+  Ok(bins)
+}
 
-  return TTestResult { p, t };
+// Parses the value of a command-line option.
+fn parse_arg<T: FromStr>(name: &str, value: &str) -> Result<T>
+where
+  T::Err: fmt::Display,
+{
+  value.trim().parse::<T>().map_err(|e| Error::Argument {
+    name: name.to_string(),
+    value: value.to_string(),
+    problem: e.to_string(),
+  })
 }
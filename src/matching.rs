@@ -0,0 +1,794 @@
+use crate::data::Instance;
+use crate::distance::distance;
+use statistical::mean;
+use std::cmp::Ordering;
+
+/// How each Small-Group record is assigned a Big-Group partner. Coarsened
+/// exact matching pairs no records, and runs through `coarsened_exact_match`
+/// instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Matcher {
+  /// Nearest remaining Big-Group record, in shuffled Small-Group order.
+  Greedy,
+  /// Minimum total distance over all pairs (Hungarian algorithm).
+  Optimal,
+}
+
+impl Matcher {
+  pub fn from_arg(arg: &str) -> Matcher {
+    match arg {
+      "optimal" => Matcher::Optimal,
+      _ => Matcher::Greedy,
+    }
+  }
+
+  /// Pairs the records of `small_boys` at positions `small`, in that order,
+  /// with records of `big_boys` at positions `big`. Both must have been placed
+  /// in the matching space (see `Dataset::place`).
+  pub fn match_records(
+    self,
+    small_boys: &[Instance],
+    big_boys: &[Instance],
+    small: Vec<usize>,
+    big: Vec<usize>,
+    options: &MatchOptions,
+  ) -> Matching {
+    match self {
+      Matcher::Greedy => greedy_match(small_boys, big_boys, small, big, options),
+      // With replacement every record simply takes its nearest controls,
+      // which is already the minimum total distance.
+      Matcher::Optimal if options.with_replacement => {
+        greedy_match(small_boys, big_boys, small, big, options)
+      }
+      Matcher::Optimal => optimal_match(small_boys, big_boys, small, big, options),
+    }
+  }
+}
+
+/// Settings shared by every matcher.
+#[derive(Debug, Clone, Copy)]
+pub struct MatchOptions {
+  /// Largest allowed distance, already resolved to absolute units.
+  pub caliper: Option<f64>,
+  /// Number of Big-Group controls wanted for each Small-Group record; at
+  /// least 1.
+  pub ratio: usize,
+  /// Whether a Big-Group record can be a control for more than one record.
+  pub with_replacement: bool,
+}
+
+/// A Small-Group record and the Big-Group control(s) matched to it, by their
+/// positions among the records given to the matcher. With a ratio above 1
+/// there can be several controls; statistics use their average.
+#[derive(Debug)]
+pub struct Match {
+  pub bigs: Vec<usize>,
+  pub small: usize,
+}
+
+impl Match {
+  /// The average of `value` over the controls, found in `big_boys`, that
+  /// have one (not NaN), or NaN if none does.
+  pub fn big_mean<F: Fn(&Instance) -> f64>(&self, big_boys: &[Instance], value: F) -> f64 {
+    let values = self
+      .bigs
+      .iter()
+      .map(|&b| value(&big_boys[b]))
+      .filter(|v| !v.is_nan())
+      .collect::<Vec<f64>>();
+    if values.is_empty() {
+      return f64::NAN;
+    }
+    mean(&values[..])
+  }
+}
+
+/// The result of one matching pass. Small-Group records left without a partner
+/// (no Big-Group record within the caliper, or the Big-Group ran out) are kept
+/// in `unmatched` and do not take part in any statistics.
+#[derive(Debug)]
+pub struct Matching {
+  pub matches: Vec<Match>,
+  /// Positions of the unmatched Small-Group records.
+  pub unmatched: Vec<usize>,
+}
+
+// The Big-Group records still available to the greedy matcher, indexed for
+// nearest-neighbour lookups. Ties between equally distant records are broken
+// as the original matcher did by re-sorting the remaining records (stably) by
+// distance from every record it looked up and taking from the end: in favour
+// of the record closer to the previous lookup, then to the one before, and so
+// on, then of the later input position.
+//
+// One-dimensional coordinates (a single covariate or a propensity score) are
+// kept sorted, and removed positions are skipped with "next alive" pointers in
+// each direction (union-find with path compression). Records at the same
+// coordinate are tied against every lookup, so the rule picks them by
+// decreasing input position, and a lookup only takes the k last alive records
+// of each coordinate value it passes: it costs O(log n + k log k), however
+// many records share a value, and a removal near O(1). Several coordinates
+// fall back to a linear scan with partial selection.
+struct NearestIndex<'a> {
+  big_boys: &'a [Instance],
+  // Position in `big_boys` of each record, in input order; None once removed
+  records: Vec<Option<usize>>,
+  // (coordinate, input position), sorted; empty unless one-dimensional
+  sorted: Vec<(f64, usize)>,
+  // right[p]: sorted position at or after p that may still be alive;
+  // left[p + 1]: at or before p. `sorted.len()` and 0 are "none" sentinels.
+  right: Vec<usize>,
+  left: Vec<usize>,
+  // Sorted position of each input position
+  rank: Vec<usize>,
+  // First and last sorted position with the same coordinate as each one
+  run_start: Vec<usize>,
+  run_end: Vec<usize>,
+  // Coordinates of every record looked up so far, in order
+  history: Vec<Vec<f64>>,
+}
+
+impl<'a> NearestIndex<'a> {
+  fn new(big_boys: &'a [Instance], big: Vec<usize>) -> NearestIndex<'a> {
+    let one_dimensional = big.iter().all(|&b| big_boys[b].coords.len() == 1);
+
+    let mut sorted = if one_dimensional {
+      big
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| (big_boys[b].coords[0], i))
+        .collect::<Vec<(f64, usize)>>()
+    } else {
+      Vec::new()
+    };
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+    let mut rank = vec![0; sorted.len()];
+    for (p, (_, i)) in sorted.iter().enumerate() {
+      rank[*i] = p;
+    }
+    let mut run_start = vec![0; sorted.len()];
+    for p in 1..sorted.len() {
+      run_start[p] = if sorted[p].0 == sorted[p - 1].0 {
+        run_start[p - 1]
+      } else {
+        p
+      };
+    }
+    let mut run_end = (0..sorted.len()).collect::<Vec<usize>>();
+    for p in (0..sorted.len().saturating_sub(1)).rev() {
+      if sorted[p].0 == sorted[p + 1].0 {
+        run_end[p] = run_end[p + 1];
+      }
+    }
+
+    NearestIndex {
+      right: (0..=sorted.len()).collect(),
+      left: (0..=sorted.len()).collect(),
+      big_boys,
+      records: big.into_iter().map(Some).collect(),
+      sorted,
+      rank,
+      run_start,
+      run_end,
+      history: Vec::new(),
+    }
+  }
+
+  // First alive sorted position at or after p, if any.
+  fn find_right(&mut self, p: usize) -> Option<usize> {
+    let mut root = p;
+    while self.right[root] != root {
+      root = self.right[root];
+    }
+    let mut p = p;
+    while self.right[p] != root {
+      let next = self.right[p];
+      self.right[p] = root;
+      p = next;
+    }
+
+    if root < self.sorted.len() {
+      Some(root)
+    } else {
+      None
+    }
+  }
+
+  // Last alive sorted position at or before p - 1, if any (p is 1-based here
+  // so that 0 can mean "none").
+  fn find_left(&mut self, p: usize) -> Option<usize> {
+    let mut root = p;
+    while self.left[root] != root {
+      root = self.left[root];
+    }
+    let mut p = p;
+    while self.left[p] != root {
+      let next = self.left[p];
+      self.left[p] = root;
+      p = next;
+    }
+
+    if root > 0 {
+      Some(root - 1)
+    } else {
+      None
+    }
+  }
+
+  // Orders two equally distant records by the tie rule above, first one
+  // first.
+  fn tie_break(&self, a: usize, b: usize) -> Ordering {
+    let (a_coords, b_coords) = (&self.get(a).coords, &self.get(b).coords);
+    // Records at the same place are tied against every lookup
+    if a_coords != b_coords {
+      for earlier in self.history.iter().rev() {
+        let ordering = coords_distance(a_coords, earlier)
+          .partial_cmp(&coords_distance(b_coords, earlier))
+          .unwrap_or(Ordering::Equal);
+        if ordering != Ordering::Equal {
+          return ordering;
+        }
+      }
+    }
+
+    b.cmp(&a)
+  }
+
+  // Input positions of the (up to) k alive records nearest to `record`,
+  // nearest first, with ties broken as described above.
+  fn nearest(&mut self, record: &Instance, k: usize) -> Vec<usize> {
+    let mut candidates: Vec<(f64, usize)> = if self.sorted.is_empty() {
+      self
+        .records
+        .iter()
+        .enumerate()
+        .filter_map(|(i, b)| b.map(|b| (distance(&self.big_boys[b], record), i)))
+        .collect()
+    } else {
+      // Walk outwards from the target one coordinate value at a time, in
+      // order of increasing distance, taking the (up to) k alive records of
+      // each value with the highest input positions. Stop once the next value
+      // is strictly further than the k-th record, so every value tied with it
+      // is a candidate.
+      let target = record.coords[0];
+      let start = self.sorted.partition_point(|(value, _)| *value < target);
+      let mut left = self.find_left(start);
+      let mut right = self.find_right(start);
+      let mut candidates: Vec<(f64, usize)> = Vec::new();
+
+      loop {
+        let left_distance = left.map(|p| target - self.sorted[p].0);
+        let right_distance = right.map(|p| self.sorted[p].0 - target);
+        let take_left = match (left_distance, right_distance) {
+          (None, None) => break,
+          (Some(_), None) => true,
+          (None, Some(_)) => false,
+          (Some(l), Some(r)) => l <= r,
+        };
+        let next_distance = if take_left {
+          left_distance
+        } else {
+          right_distance
+        }
+        .unwrap();
+
+        if candidates.len() >= k && next_distance > candidates[k - 1].0 {
+          break;
+        }
+
+        // The last alive record of the value, walking down
+        let (first, last) = if take_left {
+          let p = left.unwrap();
+          (self.run_start[p], p)
+        } else {
+          let p = right.unwrap();
+          (p, self.find_left(self.run_end[p] + 1).unwrap())
+        };
+        let mut at = Some(last);
+        for _ in 0..k {
+          match at {
+            Some(p) if p >= first => {
+              candidates.push((next_distance, self.sorted[p].1));
+              at = self.find_left(p);
+            }
+            _ => break,
+          }
+        }
+
+        if take_left {
+          left = self.find_left(first);
+        } else {
+          right = self.find_right(self.run_end[first] + 1);
+        }
+      }
+
+      candidates
+    };
+
+    let by_distance = |a: &(f64, usize), b: &(f64, usize)| {
+      a.0
+        .partial_cmp(&b.0)
+        .unwrap_or(Ordering::Equal)
+        .then_with(|| self.tie_break(a.1, b.1))
+    };
+    if candidates.len() > k && k > 0 {
+      candidates.select_nth_unstable_by(k - 1, by_distance);
+    }
+    candidates.truncate(k);
+    candidates.sort_by(by_distance);
+    self.history.push(record.coords.clone());
+
+    candidates.into_iter().map(|(_, i)| i).collect()
+  }
+
+  // Position in `big_boys` of the record at input position i.
+  fn position(&self, i: usize) -> usize {
+    self.records[i].unwrap()
+  }
+
+  fn get(&self, i: usize) -> &'a Instance {
+    &self.big_boys[self.position(i)]
+  }
+
+  fn remove(&mut self, i: usize) -> usize {
+    if !self.sorted.is_empty() {
+      let p = self.rank[i];
+      self.right[p] = p + 1;
+      self.left[p + 1] = p;
+    }
+
+    self.records[i].take().unwrap()
+  }
+}
+
+// The distance between two points of the matching space, as `distance`
+// computes it between records.
+fn coords_distance(a: &[f64], b: &[f64]) -> f64 {
+  a.iter()
+    .zip(b.iter())
+    .map(|(x, y)| (x - y).powi(2))
+    .sum::<f64>()
+    .sqrt()
+}
+
+fn greedy_match(
+  small_boys: &[Instance],
+  big_boys: &[Instance],
+  small: Vec<usize>,
+  big: Vec<usize>,
+  options: &MatchOptions,
+) -> Matching {
+  let mut matches: Vec<Match> = Vec::new();
+  let mut unmatched: Vec<usize> = Vec::new();
+  let mut index = NearestIndex::new(big_boys, big);
+
+  for s in small {
+    let record = &small_boys[s];
+    // Take up to `ratio` of the closest remaining records that fall inside
+    // the caliper.
+    let nearest = index
+      .nearest(record, options.ratio)
+      .into_iter()
+      .take_while(|&i| {
+        options
+          .caliper
+          .is_none_or(|width| distance(index.get(i), record) <= width)
+      })
+      .collect::<Vec<usize>>();
+
+    if nearest.is_empty() {
+      unmatched.push(s);
+      continue;
+    }
+
+    // Controls come in the original matcher's order: nearest first when
+    // reused, and nearest last when taken off the end of its vector
+    let bigs = if options.with_replacement {
+      nearest.iter().map(|&i| index.position(i)).collect()
+    } else {
+      nearest.iter().rev().map(|&i| index.remove(i)).collect()
+    };
+
+    matches.push(Match { small: s, bigs });
+  }
+
+  Matching { matches, unmatched }
+}
+
+// Solves the rectangular assignment problem of the `small` records (rows)
+// onto the `big` records (columns) with cost equal to their distance, using
+// the O(n^2 m) potentials formulation of the Hungarian algorithm. Each
+// Small-Group record gets `ratio` rows so it can receive that many controls.
+// Matches are returned in `small` order.
+//
+// When a caliper is given, or there are fewer Big-Group records than rows,
+// one dummy "unmatched" column per row is added. Its cost is larger than any
+// total of real distances, so the solution first fills as many control slots
+// as the caliper allows and then minimizes their total distance.
+fn optimal_match(
+  small_boys: &[Instance],
+  big_boys: &[Instance],
+  small: Vec<usize>,
+  big: Vec<usize>,
+  options: &MatchOptions,
+) -> Matching {
+  let caliper = options.caliper;
+  let ratio = options.ratio;
+  let n = small.len() * ratio;
+  let real_columns = big.len();
+  let m = if caliper.is_some() || n > real_columns {
+    real_columns + n
+  } else {
+    real_columns
+  };
+
+  let max_distance = small
+    .iter()
+    .flat_map(|&a| {
+      big
+        .iter()
+        .map(move |&b| distance(&small_boys[a], &big_boys[b]))
+    })
+    .fold(0.0, f64::max);
+  let unmatched_cost = max_distance * (n as f64) + 1.0;
+
+  let cost = |i: usize, j: usize| {
+    if j > real_columns {
+      return unmatched_cost;
+    }
+    let distance = distance(&small_boys[small[(i - 1) / ratio]], &big_boys[big[j - 1]]);
+    match caliper {
+      Some(width) if distance > width => 2.0 * unmatched_cost,
+      _ => distance,
+    }
+  };
+
+  // Row and column potentials, and the row currently assigned to each column.
+  // Index 0 is a sentinel; rows and columns are 1-based.
+  let mut u = vec![0.0; n + 1];
+  let mut v = vec![0.0; m + 1];
+  let mut assigned_row = vec![0usize; m + 1];
+  let mut way = vec![0usize; m + 1];
+
+  for i in 1..=n {
+    assigned_row[0] = i;
+    let mut j0 = 0;
+    let mut minv = vec![f64::INFINITY; m + 1];
+    let mut used = vec![false; m + 1];
+
+    // Grow an alternating tree from row i until it reaches a free column.
+    loop {
+      used[j0] = true;
+      let i0 = assigned_row[j0];
+      let mut delta = f64::INFINITY;
+      let mut j1 = 0;
+
+      for j in 1..=m {
+        if !used[j] {
+          let cur = cost(i0, j) - u[i0] - v[j];
+          if cur < minv[j] {
+            minv[j] = cur;
+            way[j] = j0;
+          }
+          if minv[j] < delta {
+            delta = minv[j];
+            j1 = j;
+          }
+        }
+      }
+
+      for j in 0..=m {
+        if used[j] {
+          u[assigned_row[j]] += delta;
+          v[j] -= delta;
+        } else {
+          minv[j] -= delta;
+        }
+      }
+
+      j0 = j1;
+      if assigned_row[j0] == 0 {
+        break;
+      }
+    }
+
+    // Flip the augmenting path back to the root.
+    loop {
+      let j1 = way[j0];
+      assigned_row[j0] = assigned_row[j1];
+      j0 = j1;
+      if j0 == 0 {
+        break;
+      }
+    }
+  }
+
+  let mut partners: Vec<Vec<usize>> = vec![Vec::new(); small.len()];
+  for j in 1..=real_columns {
+    if assigned_row[j] != 0 {
+      partners[(assigned_row[j] - 1) / ratio].push(j - 1);
+    }
+  }
+
+  // Pairs outside the caliper cost more than a dummy column, so every real
+  // column in the solution is within the caliper.
+  let mut matches: Vec<Match> = Vec::new();
+  let mut unmatched: Vec<usize> = Vec::new();
+  for (&s, columns) in small.iter().zip(partners) {
+    if columns.is_empty() {
+      unmatched.push(s);
+    } else {
+      matches.push(Match {
+        small: s,
+        bigs: columns.iter().map(|&j| big[j]).collect(),
+      });
+    }
+  }
+
+  Matching { matches, unmatched }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::rngs::StdRng;
+  use rand::{Rng, SeedableRng};
+
+  fn record(id: String, coords: Vec<f64>) -> Instance {
+    Instance {
+      id,
+      coords,
+      ..Instance::default()
+    }
+  }
+
+  // IDs of every match's record and controls, and of the unmatched records
+  type Ids = (Vec<(String, Vec<String>)>, Vec<String>);
+
+  // The greedy matcher as it was before the index: sort the remaining
+  // Big-Group records by decreasing distance and take from the end.
+  fn sort_based_match(
+    small_boys: Vec<Instance>,
+    mut big_boys: Vec<Instance>,
+    options: &MatchOptions,
+  ) -> Ids {
+    let mut matches: Vec<(String, Vec<String>)> = Vec::new();
+    let mut unmatched: Vec<String> = Vec::new();
+
+    for record in small_boys {
+      big_boys.sort_by(|a, b| {
+        distance(a, &record)
+          .partial_cmp(&distance(b, &record))
+          .unwrap_or(Ordering::Equal)
+          .reverse()
+      });
+
+      let within_caliper = big_boys
+        .iter()
+        .rev()
+        .take(options.ratio)
+        .take_while(|closest| {
+          options
+            .caliper
+            .is_none_or(|width| distance(closest, &record) <= width)
+        })
+        .count();
+
+      if within_caliper == 0 {
+        unmatched.push(record.id);
+        continue;
+      }
+
+      let bigs = if options.with_replacement {
+        big_boys
+          .iter()
+          .rev()
+          .take(within_caliper)
+          .cloned()
+          .collect()
+      } else {
+        big_boys.split_off(big_boys.len() - within_caliper)
+      };
+
+      matches.push((record.id, bigs.into_iter().map(|e| e.id).collect()));
+    }
+
+    (matches, unmatched)
+  }
+
+  fn greedy_ids(small_boys: &[Instance], big_boys: &[Instance], options: &MatchOptions) -> Ids {
+    let matching = greedy_match(
+      small_boys,
+      big_boys,
+      (0..small_boys.len()).collect(),
+      (0..big_boys.len()).collect(),
+      options,
+    );
+
+    (
+      matching
+        .matches
+        .iter()
+        .map(|e| {
+          (
+            small_boys[e.small].id.clone(),
+            e.bigs.iter().map(|&b| big_boys[b].id.clone()).collect(),
+          )
+        })
+        .collect(),
+      matching
+        .unmatched
+        .iter()
+        .map(|&s| small_boys[s].id.clone())
+        .collect(),
+    )
+  }
+
+  #[test]
+  fn greedy_matches_like_the_sort_based_matcher() {
+    let mut rng = StdRng::seed_from_u64(7);
+    for trial in 0..400 {
+      // Few distinct integer coordinates, so ties are everywhere
+      let dimensions = 1 + trial % 2;
+      let small_count = rng.gen_range(1, 12);
+      let big_count = rng.gen_range(1, 30);
+      let mut point = |prefix: &str, i: usize| {
+        record(
+          format!("{}{}", prefix, i),
+          (0..dimensions)
+            .map(|_| f64::from(rng.gen_range(0, 8)))
+            .collect(),
+        )
+      };
+      let small_boys = (0..small_count)
+        .map(|i| point("s", i))
+        .collect::<Vec<Instance>>();
+      let big_boys = (0..big_count)
+        .map(|i| point("b", i))
+        .collect::<Vec<Instance>>();
+      let options = MatchOptions {
+        caliper: if trial % 3 == 0 { Some(1.5) } else { None },
+        ratio: 1 + trial % 3,
+        with_replacement: trial % 5 == 0,
+      };
+
+      assert_eq!(
+        greedy_ids(&small_boys, &big_boys, &options),
+        sort_based_match(small_boys, big_boys, &options),
+        "trial {}",
+        trial
+      );
+    }
+  }
+
+  #[test]
+  fn greedy_breaks_ties_towards_the_previous_record() {
+    let big_boys = [10.0, 12.0, 30.0, 32.0]
+      .iter()
+      .enumerate()
+      .map(|(i, x)| record(i.to_string(), vec![*x]))
+      .collect::<Vec<Instance>>();
+    let options = MatchOptions {
+      caliper: None,
+      ratio: 1,
+      with_replacement: false,
+    };
+
+    let small_boys = vec![
+      record("a".to_string(), vec![11.0]),
+      record("b".to_string(), vec![31.0]),
+    ];
+    let (matches, _) = greedy_ids(&small_boys, &big_boys, &options);
+    assert_eq!(matches[0].1, vec!["1"]);
+    assert_eq!(matches[1].1, vec!["2"]);
+
+    let small_boys = vec![
+      record("b".to_string(), vec![31.0]),
+      record("a".to_string(), vec![11.0]),
+    ];
+    let (matches, _) = greedy_ids(&small_boys, &big_boys, &options);
+    assert_eq!(matches[0].1, vec!["3"]);
+    assert_eq!(matches[1].1, vec!["1"]);
+  }
+
+  // Total distance of the controls of every match
+  fn total_distance(small_boys: &[Instance], big_boys: &[Instance], matching: &Matching) -> f64 {
+    matching
+      .matches
+      .iter()
+      .flat_map(|e| {
+        e.bigs
+          .iter()
+          .map(move |&b| distance(&small_boys[e.small], &big_boys[b]))
+      })
+      .sum()
+  }
+
+  #[test]
+  fn optimal_beats_greedy_on_a_known_assignment() {
+    // Greedy gives 2 its nearest, 1.5, leaving 3 for 0 (total 3.5); the
+    // optimum is 2 with 3 and 0 with 1.5 (total 2.5)
+    let small_boys = vec![
+      record("a".to_string(), vec![2.0]),
+      record("b".to_string(), vec![0.0]),
+    ];
+    let big_boys = vec![
+      record("x".to_string(), vec![1.5]),
+      record("y".to_string(), vec![3.0]),
+    ];
+    let options = MatchOptions {
+      caliper: None,
+      ratio: 1,
+      with_replacement: false,
+    };
+
+    let greedy =
+      Matcher::Greedy.match_records(&small_boys, &big_boys, vec![0, 1], vec![0, 1], &options);
+    assert!((total_distance(&small_boys, &big_boys, &greedy) - 3.5).abs() < 1e-12);
+
+    let optimal =
+      Matcher::Optimal.match_records(&small_boys, &big_boys, vec![0, 1], vec![0, 1], &options);
+    let controls = optimal
+      .matches
+      .iter()
+      .map(|e| (e.small, e.bigs.clone()))
+      .collect::<Vec<(usize, Vec<usize>)>>();
+    assert_eq!(controls, vec![(0, vec![1]), (1, vec![0])]);
+    assert!((total_distance(&small_boys, &big_boys, &optimal) - 2.5).abs() < 1e-12);
+  }
+
+  #[test]
+  fn optimal_matches_the_best_assignment_by_enumeration() {
+    let mut rng = StdRng::seed_from_u64(22);
+    let options = MatchOptions {
+      caliper: None,
+      ratio: 1,
+      with_replacement: false,
+    };
+
+    for _ in 0..200 {
+      let n = rng.gen_range(1, 5);
+      let m = rng.gen_range(n, 7);
+      let place = |rng: &mut StdRng, k: usize| {
+        (0..k)
+          .map(|i| {
+            record(
+              i.to_string(),
+              vec![rng.gen_range(0.0, 10.0), rng.gen_range(0.0, 10.0)],
+            )
+          })
+          .collect::<Vec<Instance>>()
+      };
+      let small_boys = place(&mut rng, n);
+      let big_boys = place(&mut rng, m);
+
+      // Every way to give each Small-Group record its own control
+      let mut best = f64::INFINITY;
+      let mut stack: Vec<(Vec<usize>, f64)> = vec![(Vec::new(), 0.0)];
+      while let Some((taken, total)) = stack.pop() {
+        if taken.len() == n {
+          best = best.min(total);
+          continue;
+        }
+        for b in (0..m).filter(|b| !taken.contains(b)) {
+          let mut next = taken.clone();
+          next.push(b);
+          stack.push((
+            next,
+            total + distance(&small_boys[taken.len()], &big_boys[b]),
+          ));
+        }
+      }
+
+      let optimal = Matcher::Optimal.match_records(
+        &small_boys,
+        &big_boys,
+        (0..n).collect(),
+        (0..m).collect(),
+        &options,
+      );
+      assert_eq!(optimal.matches.len(), n);
+      assert!((total_distance(&small_boys, &big_boys, &optimal) - best).abs() < 1e-9);
+    }
+  }
+}
@@ -0,0 +1,173 @@
+use crate::data::Instance;
+use crate::error::{Error, Result};
+use crate::schema::Schema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// What to do with records whose covariates or outcomes are missing (blank or
+/// `NA`).
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub enum MissingPolicy {
+  /// Drop every record with a missing value.
+  #[serde(rename = "drop-row")]
+  DropRow,
+  /// Keep each record for whatever does not need its missing values: it is
+  /// only left out of matching when it lacks a matching covariate, and out of
+  /// an outcome's statistics when it, or all of its controls, lack that
+  /// outcome.
+  #[serde(rename = "drop-pairwise")]
+  DropPairwise,
+  /// Replace each missing value with the mean of its column over the records
+  /// of the same group that have one, so imputing does not pull the groups
+  /// towards each other.
+  #[serde(rename = "impute-mean")]
+  ImputeMean,
+}
+
+impl MissingPolicy {
+  pub fn from_arg(arg: &str) -> MissingPolicy {
+    match arg {
+      "drop-pairwise" => MissingPolicy::DropPairwise,
+      "impute-mean" => MissingPolicy::ImputeMean,
+      _ => MissingPolicy::DropRow,
+    }
+  }
+}
+
+impl fmt::Display for MissingPolicy {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let arg = match self {
+      MissingPolicy::DropRow => "drop-row",
+      MissingPolicy::DropPairwise => "drop-pairwise",
+      MissingPolicy::ImputeMean => "impute-mean",
+    };
+    write!(f, "{}", arg)
+  }
+}
+
+/// What a missing-data policy did to the records.
+#[derive(Debug, Serialize, Clone)]
+pub struct MissingReport {
+  pub policy: MissingPolicy,
+  /// Missing covariate and outcome values, over all records.
+  pub missing_values: usize,
+  /// Records with at least one missing value.
+  pub incomplete_records: usize,
+  /// Records removed before matching.
+  pub excluded_records: usize,
+  /// Values filled in with their column mean within the group.
+  pub imputed_values: usize,
+}
+
+/// Whether a cell counts as missing.
+pub fn is_missing(value: &str) -> bool {
+  let value = value.trim();
+  value.is_empty() || value == "NA"
+}
+
+// Where a column is held on each record: its covariate position, its outcome
+// position, or both.
+type Slot = (Option<usize>, Option<usize>);
+
+/// Applies `policy` to the covariates and outcomes of `records`, where
+/// missing values are NaN. Missing outcomes that are kept (`DropPairwise`)
+/// stay NaN. Fails to impute a column that has no values at all in a group
+/// that is missing some.
+pub fn apply_missing_policy(
+  records: Vec<Instance>,
+  schema: &Schema,
+  policy: MissingPolicy,
+) -> Result<(Vec<Instance>, MissingReport)> {
+  let mut columns = schema
+    .covariates
+    .iter()
+    .chain(schema.outcomes.iter())
+    .collect::<Vec<&String>>();
+  columns.sort();
+  columns.dedup();
+
+  let slots = columns
+    .iter()
+    .map(|c| {
+      (
+        schema.covariates.iter().position(|e| e == *c),
+        schema.outcomes.iter().position(|e| e == *c),
+      )
+    })
+    .collect::<Vec<Slot>>();
+  let value = |record: &Instance, slot: Slot| match slot {
+    (Some(k), _) => record.covariates[k],
+    (None, Some(k)) => record.outcomes[k],
+    (None, None) => unreachable!(),
+  };
+
+  let missing_in = |record: &Instance, slots: &[Slot]| {
+    slots
+      .iter()
+      .filter(|slot| value(record, **slot).is_nan())
+      .count()
+  };
+  let missing_values = records.iter().map(|e| missing_in(e, &slots)).sum();
+  let incomplete_records = records.iter().filter(|e| missing_in(e, &slots) > 0).count();
+  let total = records.len();
+
+  let mut imputed_values = 0;
+  let records = match policy {
+    MissingPolicy::DropRow => records
+      .into_iter()
+      .filter(|e| missing_in(e, &slots) == 0)
+      .collect::<Vec<Instance>>(),
+    MissingPolicy::DropPairwise => records
+      .into_iter()
+      .filter(|e| !e.covariates.iter().any(|x| x.is_nan()))
+      .collect(),
+    MissingPolicy::ImputeMean => {
+      let mut records = records;
+      for (&slot, column) in slots.iter().zip(columns.iter()) {
+        // Sum and count of the values present in each group
+        let mut present: BTreeMap<String, (f64, usize)> = BTreeMap::new();
+        for record in records.iter() {
+          let entry = present.entry(record.condition.clone()).or_insert((0.0, 0));
+          let x = value(record, slot);
+          if !x.is_nan() {
+            entry.0 += x;
+            entry.1 += 1;
+          }
+        }
+
+        for record in records.iter_mut() {
+          if value(record, slot).is_nan() {
+            let column_mean = match present[&record.condition] {
+              (_, 0) => {
+                return Err(Error::NothingToImpute {
+                  column: column.to_string(),
+                  group: record.condition.clone(),
+                })
+              }
+              (sum, count) => sum / count as f64,
+            };
+            if let Some(k) = slot.0 {
+              record.covariates[k] = column_mean;
+            }
+            if let Some(k) = slot.1 {
+              record.outcomes[k] = column_mean;
+            }
+            imputed_values += 1;
+          }
+        }
+      }
+      records
+    }
+  };
+
+  let report = MissingReport {
+    policy,
+    missing_values,
+    incomplete_records,
+    excluded_records: total - records.len(),
+    imputed_values,
+  };
+
+  Ok((records, report))
+}
@@ -0,0 +1,154 @@
+use crate::error::{Error, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// File format of the iterations, summary and pairs files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+  /// A header row, then one row per record.
+  Csv,
+  /// A single array with one object per record.
+  Json,
+  /// One object per line (JSON Lines).
+  Jsonl,
+}
+
+impl Format {
+  pub fn from_arg(arg: &str) -> Format {
+    match arg {
+      "json" => Format::Json,
+      "jsonl" => Format::Jsonl,
+      _ => Format::Csv,
+    }
+  }
+
+  /// The usual file extension of the format.
+  pub fn extension(self) -> &'static str {
+    match self {
+      Format::Csv => "csv",
+      Format::Json => "json",
+      Format::Jsonl => "jsonl",
+    }
+  }
+}
+
+// Where the records go: a CSV writer, or the file itself for JSON.
+enum Sink {
+  Csv(Box<csv::Writer<File>>),
+  Json(BufWriter<File>),
+}
+
+/// Writes records to a file in one of the output formats. Call `finish` once
+/// every record is written.
+pub struct RecordWriter {
+  filename: String,
+  format: Format,
+  sink: Sink,
+  written: usize,
+}
+
+impl RecordWriter {
+  /// Creates (or truncates) `filename`.
+  pub fn create(filename: &str, format: Format) -> Result<RecordWriter> {
+    let sink = match format {
+      Format::Csv => Sink::Csv(Box::new(
+        csv::Writer::from_path(filename).map_err(|e| Error::csv(filename, e))?,
+      )),
+      Format::Json | Format::Jsonl => Sink::Json(BufWriter::new(
+        File::create(filename).map_err(|e| Error::io(filename, e))?,
+      )),
+    };
+
+    Ok(RecordWriter {
+      filename: filename.to_string(),
+      format,
+      sink,
+      written: 0,
+    })
+  }
+
+  /// Writes `value`, as a CSV row of its fields or as a JSON object.
+  pub fn write<T: Serialize>(&mut self, value: &T) -> Result<()> {
+    let filename = &self.filename;
+    match &mut self.sink {
+      Sink::Csv(writer) => writer
+        .serialize(value)
+        .map_err(|e| Error::csv(filename, e))?,
+      Sink::Json(_) => self.write_json(value)?,
+    }
+    self.written += 1;
+
+    Ok(())
+  }
+
+  /// Writes `value` as a JSON object, or in CSV as the row `record`, with
+  /// `headers` before the first row. For records whose nested fields are
+  /// flattened into columns.
+  pub fn write_row<T: Serialize>(
+    &mut self,
+    value: &T,
+    headers: Vec<String>,
+    record: Vec<String>,
+  ) -> Result<()> {
+    let filename = &self.filename;
+    match &mut self.sink {
+      Sink::Csv(writer) => {
+        if self.written == 0 {
+          writer
+            .write_record(headers)
+            .map_err(|e| Error::csv(filename, e))?;
+        }
+        writer
+          .write_record(record)
+          .map_err(|e| Error::csv(filename, e))?;
+      }
+      Sink::Json(_) => self.write_json(value)?,
+    }
+    self.written += 1;
+
+    Ok(())
+  }
+
+  /// Closes the JSON array and flushes the file.
+  pub fn finish(self) -> Result<()> {
+    let filename = self.filename;
+    match self.sink {
+      Sink::Csv(mut writer) => writer.flush().map_err(|e| Error::io(&filename, e)),
+      Sink::Json(mut writer) => {
+        if self.format == Format::Json {
+          let close = if self.written == 0 { "[]\n" } else { "\n]\n" };
+          writer
+            .write_all(close.as_bytes())
+            .map_err(|e| Error::io(&filename, e))?;
+        }
+        writer.flush().map_err(|e| Error::io(&filename, e))
+      }
+    }
+  }
+
+  // Writes one JSON object: an element of the array, or a line of its own.
+  fn write_json<T: Serialize>(&mut self, value: &T) -> Result<()> {
+    let filename = &self.filename;
+    let writer = match &mut self.sink {
+      Sink::Json(writer) => writer,
+      Sink::Csv(_) => unreachable!(),
+    };
+    let separator = match (self.format, self.written) {
+      (Format::Json, 0) => "[\n",
+      (Format::Json, _) => ",\n",
+      _ => "",
+    };
+    writer
+      .write_all(separator.as_bytes())
+      .map_err(|e| Error::io(filename, e))?;
+    serde_json::to_writer(&mut *writer, value).map_err(|e| Error::json(filename, e))?;
+    if self.format == Format::Jsonl {
+      writer
+        .write_all(b"\n")
+        .map_err(|e| Error::io(filename, e))?;
+    }
+
+    Ok(())
+  }
+}
@@ -0,0 +1,139 @@
+use crate::data::Dataset;
+use serde::Serialize;
+
+/// How often one Big-Group record was selected as a control.
+#[derive(Debug, Serialize, Clone)]
+pub struct ControlUse {
+  pub arm: String,
+  pub id: String,
+  /// Iterations in which the record was a control at least once.
+  pub selected: usize,
+  /// `selected` as a share of all iterations.
+  pub share: f64,
+}
+
+/// How much the selected controls change from one iteration to the next.
+#[derive(Debug, Serialize, Clone)]
+pub struct ControlReuse {
+  /// Big-Group records available as controls.
+  pub pool: usize,
+  /// Records selected in at least one iteration.
+  pub ever_selected: usize,
+  /// Records selected in every iteration.
+  pub always_selected: usize,
+  /// Jaccard overlap (shared over either) of the controls selected in two
+  /// consecutive iterations.
+  pub overlap_mean: f64,
+  pub overlap_stdev: f64,
+  pub overlap_min: f64,
+  pub overlap_max: f64,
+
+  // Written to the control reuse file rather than with the summary.
+  #[serde(skip)]
+  pub controls: Vec<ControlUse>,
+}
+
+/// The controls selected in one iteration, as a bitset over the Big-Group
+/// positions of `dataset`.
+pub(crate) fn selected_set(dataset: &Dataset, controls: impl Iterator<Item = usize>) -> Vec<u64> {
+  let mut set = vec![0u64; dataset.big.len().div_ceil(64)];
+  for p in controls {
+    set[p / 64] |= 1 << (p % 64);
+  }
+  set
+}
+
+// Mean, variance, minimum and maximum of a stream of values, updated one
+// value at a time (Welford's algorithm) so the values need not be kept.
+struct Moments {
+  count: usize,
+  mean: f64,
+  // Sum of squared deviations from the running mean
+  squares: f64,
+  min: f64,
+  max: f64,
+}
+
+impl Moments {
+  fn new() -> Moments {
+    Moments {
+      count: 0,
+      mean: 0.0,
+      squares: 0.0,
+      min: f64::INFINITY,
+      max: f64::NEG_INFINITY,
+    }
+  }
+
+  fn add(&mut self, x: f64) {
+    self.count += 1;
+    let delta = x - self.mean;
+    self.mean += delta / self.count as f64;
+    self.squares += delta * (x - self.mean);
+    self.min = self.min.min(x);
+    self.max = self.max.max(x);
+  }
+
+  // The sample standard deviation; NaN with fewer than two values.
+  fn stdev(&self) -> f64 {
+    if self.count > 1 {
+      (self.squares / (self.count - 1) as f64).sqrt()
+    } else {
+      f64::NAN
+    }
+  }
+}
+
+/// Counts how often each Big-Group record of `dataset` was selected in the
+/// iterations `sets`, each from `selected_set`, and how much the selections of
+/// consecutive iterations overlap. Needs at least two iterations.
+pub(crate) fn control_reuse(dataset: &Dataset, sets: &[&[u64]]) -> ControlReuse {
+  let mut counts = vec![0usize; dataset.big.len()];
+  let mut overlaps = Moments::new();
+  for (i, set) in sets.iter().enumerate() {
+    for (p, count) in counts.iter_mut().enumerate() {
+      if set[p / 64] & (1 << (p % 64)) != 0 {
+        *count += 1;
+      }
+    }
+
+    if i > 0 {
+      let (shared, either) = sets[i - 1]
+        .iter()
+        .zip(set.iter())
+        .fold((0, 0), |(shared, either), (x, y)| {
+          (shared + (x & y).count_ones(), either + (x | y).count_ones())
+        });
+      // Two empty selections are the same selection
+      overlaps.add(if either == 0 {
+        1.0
+      } else {
+        f64::from(shared) / f64::from(either)
+      });
+    }
+  }
+
+  let controls = dataset
+    .big
+    .iter()
+    .zip(counts)
+    .map(|(e, selected)| ControlUse {
+      arm: dataset.arm.clone(),
+      id: e.id.clone(),
+      selected,
+      share: selected as f64 / sets.len() as f64,
+    })
+    .collect::<Vec<ControlUse>>();
+
+  ControlReuse {
+    pool: dataset.big.len(),
+    ever_selected: controls.iter().filter(|e| e.selected > 0).count(),
+    always_selected: controls.iter().filter(|e| e.selected == sets.len()).count(),
+    overlap_mean: overlaps.mean,
+    // Two iterations give a single overlap, with no spread to speak of
+    overlap_stdev: overlaps.stdev(),
+    overlap_min: overlaps.min,
+    overlap_max: overlaps.max,
+    controls,
+  }
+}
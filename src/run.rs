@@ -0,0 +1,611 @@
+use crate::balance::{covariate_balance, Balance};
+use crate::calibration::{NullProportion, NullSummary};
+use crate::data::Dataset;
+use crate::distance::distance;
+use crate::error::Result;
+use crate::matching::{Match, MatchOptions, Matcher};
+use crate::reuse::{control_reuse, selected_set, ControlReuse};
+use crate::stats::{
+  paired_effect_size, sample_stdev, wilson_interval, Alternative, Test, TestOutcome,
+};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::Serialize;
+use statistical::mean;
+use std::collections::BTreeMap;
+
+/// How every iteration matches and tests the records.
+#[derive(Debug, Clone)]
+pub struct Settings {
+  pub matcher: Matcher,
+  pub options: MatchOptions,
+  pub alternative: Alternative,
+  /// Tests run on every outcome's matched differences.
+  pub tests: Vec<Test>,
+  /// Sign flips drawn by the permutation test.
+  pub permutations: usize,
+  /// Whether to keep every matched pair in `Output::pairs`; only needed to
+  /// write them out.
+  pub pairs: bool,
+  /// Whether to keep the controls of every iteration in `Output::selected`;
+  /// only needed for the control reuse report.
+  pub reuse: bool,
+}
+
+/// How many Small-Group records of one exact-matching stratum were matched in
+/// one iteration.
+#[derive(Debug, Serialize, Clone)]
+pub struct StratumCount {
+  pub arm: String,
+  pub iteration: usize,
+  pub stratum: String,
+  pub matched: usize,
+  pub unmatched: usize,
+}
+
+/// A Small-Group record and one of its Big-Group controls in one iteration.
+#[derive(Debug, Serialize, Clone)]
+pub struct MatchedPair {
+  pub arm: String,
+  pub iteration: usize,
+  pub small_id: String,
+  pub big_id: String,
+  /// Distance between the two in the matching space, in the units of an
+  /// absolute caliper.
+  pub distance: f64,
+}
+
+/// Means and standard deviations of one outcome over the matched records of
+/// one iteration, with each record's Big-Group controls averaged, the paired
+/// tests between them and the effect sizes of their difference.
+#[derive(Debug, Serialize, Clone)]
+pub struct OutcomeStats {
+  pub outcome: String,
+  /// Matched pairs with the outcome on both sides.
+  pub pairs: usize,
+  pub small_mean: f64,
+  pub big_mean: f64,
+  pub small_stdev: f64,
+  pub big_stdev: f64,
+  /// One entry per test, in settings order.
+  pub tests: Vec<TestOutcome>,
+  pub mean_difference: f64,
+  pub ci_lower: f64,
+  pub ci_upper: f64,
+  pub d_z: f64,
+  pub d_av: f64,
+  pub hedges_g: f64,
+}
+
+impl OutcomeStats {
+  /// Every statistic by name, in iterations file order. Columns and summaries
+  /// are generated from this list.
+  pub fn statistics(&self) -> Vec<(&'static str, f64)> {
+    let mut statistics = vec![
+      ("pairs", self.pairs as f64),
+      ("small_mean", self.small_mean),
+      ("big_mean", self.big_mean),
+      ("small_stdev", self.small_stdev),
+      ("big_stdev", self.big_stdev),
+    ];
+    for outcome in self.tests.iter() {
+      statistics.push((outcome.test.pvalue_name(), outcome.pvalue));
+      statistics.push((outcome.test.statistic_name(), outcome.statistic));
+    }
+    statistics.extend(vec![
+      ("mean_difference", self.mean_difference),
+      ("ci_lower", self.ci_lower),
+      ("ci_upper", self.ci_upper),
+      ("d_z", self.d_z),
+      ("d_av", self.d_av),
+      ("hedges_g", self.hedges_g),
+    ]);
+
+    statistics
+  }
+}
+
+/// The result of one iteration.
+#[derive(Debug, Serialize, Clone)]
+pub struct Output {
+  pub arm: String,
+  pub iteration: usize,
+  /// Seeds the shuffle of this iteration; pass it to --replay to rerun it.
+  pub seed: u64,
+
+  pub matched: usize,
+  pub unmatched: usize,
+  pub controls: usize,
+
+  /// One entry per outcome, in schema order.
+  pub outcomes: Vec<OutcomeStats>,
+  pub alternative: Alternative,
+
+  // Written to the balance, strata and pairs files rather than with the
+  // iteration.
+  #[serde(skip)]
+  pub balance: Vec<Balance>,
+  #[serde(skip)]
+  pub strata: Vec<StratumCount>,
+  #[serde(skip)]
+  pub pairs: Vec<MatchedPair>,
+  /// The records selected as controls, as a bitset over the Big-Group
+  /// positions; only kept for the control reuse report.
+  #[serde(skip)]
+  pub selected: Option<Vec<u64>>,
+}
+
+impl Output {
+  /// Column names of `record`: the fixed columns, then `<outcome>_<statistic>`
+  /// for every outcome.
+  pub fn headers(&self) -> Vec<String> {
+    let mut headers = [
+      "arm",
+      "iteration",
+      "seed",
+      "matched",
+      "unmatched",
+      "controls",
+    ]
+    .iter()
+    .map(|h| h.to_string())
+    .collect::<Vec<String>>();
+    for stats in self.outcomes.iter() {
+      for (name, _) in stats.statistics() {
+        headers.push(format!("{}_{}", stats.outcome, name));
+      }
+    }
+    headers.push("alternative".to_string());
+
+    headers
+  }
+
+  /// The iteration as one row of a CSV iterations file.
+  pub fn record(&self) -> Vec<String> {
+    let mut record = vec![
+      self.arm.clone(),
+      self.iteration.to_string(),
+      self.seed.to_string(),
+      self.matched.to_string(),
+      self.unmatched.to_string(),
+      self.controls.to_string(),
+    ];
+    for stats in self.outcomes.iter() {
+      for (_, value) in stats.statistics() {
+        record.push(value.to_string());
+      }
+    }
+    record.push(self.alternative.to_string());
+
+    record
+  }
+}
+
+/// Shuffles the Small-Group records with `seed`, matches them, summarizes
+/// every outcome and tests each of them. The permutation test draws from the
+/// same seeded generator, after the shuffle. Fails if `dataset` has not been
+/// placed in the matching space (see `Dataset::place`).
+pub fn run_iteration(
+  iteration: usize,
+  seed: u64,
+  dataset: &Dataset,
+  settings: &Settings,
+) -> Result<Output> {
+  dataset.check_placed()?;
+
+  let mut rng = StdRng::seed_from_u64(seed);
+
+  // Shuffle the order the Small-Group records are matched in
+  let mut order = (0..dataset.small.len()).collect::<Vec<usize>>();
+  order.shuffle(&mut rng);
+
+  // Match separately inside each stratum (a single one without strata
+  // columns), passing records by position
+  let mut strata: BTreeMap<&str, (Vec<usize>, Vec<usize>)> = BTreeMap::new();
+  for s in order {
+    strata
+      .entry(&dataset.small[s].stratum)
+      .or_default()
+      .0
+      .push(s);
+  }
+  for (b, record) in dataset.big.iter().enumerate() {
+    strata.entry(&record.stratum).or_default().1.push(b);
+  }
+
+  let mut matches: Vec<Match> = Vec::new();
+  let mut unmatched: Vec<usize> = Vec::new();
+  let mut stratum_counts: Vec<StratumCount> = Vec::new();
+  for (stratum, (stratum_small, stratum_big)) in strata {
+    if stratum_small.is_empty() {
+      continue;
+    }
+
+    let matching = settings.matcher.match_records(
+      &dataset.small,
+      &dataset.big,
+      stratum_small,
+      stratum_big,
+      &settings.options,
+    );
+
+    stratum_counts.push(StratumCount {
+      arm: dataset.arm.clone(),
+      iteration,
+      stratum: stratum.to_string(),
+      matched: matching.matches.len(),
+      unmatched: matching.unmatched.len(),
+    });
+    matches.extend(matching.matches);
+    unmatched.extend(matching.unmatched);
+  }
+
+  let selected = if settings.reuse {
+    Some(selected_set(
+      dataset,
+      matches.iter().flat_map(|e| e.bigs.iter().cloned()),
+    ))
+  } else {
+    None
+  };
+
+  // Only kept to be written out
+  let pairs = if settings.pairs {
+    matches
+      .iter()
+      .flat_map(|e| {
+        let small = &dataset.small[e.small];
+        e.bigs.iter().map(move |&b| MatchedPair {
+          arm: dataset.arm.clone(),
+          iteration,
+          small_id: small.id.clone(),
+          big_id: dataset.big[b].id.clone(),
+          distance: distance(small, &dataset.big[b]),
+        })
+      })
+      .collect()
+  } else {
+    Vec::new()
+  };
+
+  Ok(Output {
+    arm: dataset.arm.clone(),
+    iteration,
+    seed,
+
+    matched: matches.len(),
+    unmatched: unmatched.len(),
+    controls: matches.iter().map(|e| e.bigs.len()).sum(),
+
+    outcomes: dataset
+      .outcomes
+      .iter()
+      .enumerate()
+      .map(|(k, outcome)| {
+        // Pairs missing the outcome on either side are left out
+        let (small, big): (Vec<f64>, Vec<f64>) = matches
+          .iter()
+          .map(|e| {
+            (
+              dataset.small[e.small].outcomes[k],
+              e.big_mean(&dataset.big, |b| b.outcomes[k]),
+            )
+          })
+          .filter(|(s, b)| !s.is_nan() && !b.is_nan())
+          .unzip();
+
+        let small_mean = mean(&small[..]);
+        let big_mean = mean(&big[..]);
+        let small_stdev = sample_stdev(&small);
+        let big_stdev = sample_stdev(&big);
+        let effect = paired_effect_size(&small, &big);
+
+        OutcomeStats {
+          outcome: outcome.clone(),
+          pairs: small.len(),
+          small_mean,
+          big_mean,
+          small_stdev,
+          big_stdev,
+          tests: settings
+            .tests
+            .iter()
+            .map(|&test| {
+              // Too few pairs to test; counted apart in the summary
+              if small.len() < 2 {
+                return TestOutcome {
+                  test,
+                  pvalue: f64::NAN,
+                  statistic: f64::NAN,
+                };
+              }
+              test.run(
+                &small,
+                &big,
+                settings.alternative,
+                settings.permutations,
+                &mut rng,
+              )
+            })
+            .collect(),
+          mean_difference: effect.mean_difference,
+          ci_lower: effect.ci_lower,
+          ci_upper: effect.ci_upper,
+          d_z: effect.d_z,
+          d_av: effect.d_av,
+          hedges_g: effect.hedges_g,
+        }
+      })
+      .collect(),
+
+    alternative: settings.alternative,
+
+    balance: (0..dataset.covariates.len())
+      .map(|k| covariate_balance(iteration, k, dataset, &matches))
+      .collect(),
+    strata: stratum_counts,
+    pairs,
+    selected,
+  })
+}
+
+/// The seed of every iteration, drawn in order from `master_seed`, so any one
+/// of them can be replayed on its own.
+pub fn iteration_seeds(master_seed: u64, iterations: usize) -> Vec<u64> {
+  let mut seeder = StdRng::seed_from_u64(master_seed);
+  (0..iterations).map(|_| seeder.gen()).collect()
+}
+
+/// Runs one iteration per seed on the current rayon thread pool. Each one only
+/// depends on its seed, and results are collected in iteration order, so the
+/// output is the same for any number of threads.
+pub fn run(dataset: &Dataset, settings: &Settings, seeds: &[u64]) -> Result<Vec<Output>> {
+  seeds
+    .par_iter()
+    .enumerate()
+    .map(|(iteration, &seed)| run_iteration(iteration, seed, dataset, settings))
+    .collect()
+}
+
+/// Across-iteration balance of one matching covariate, over the iterations
+/// where it was finite.
+#[derive(Debug, Serialize, Clone)]
+pub struct BalanceSummary {
+  pub covariate: String,
+  pub smd_before: f64,
+  pub smd_after_mean: f64,
+  pub smd_after_stdev: f64,
+  pub smd_after_max_abs: f64,
+  pub variance_ratio_before: f64,
+  pub variance_ratio_after_mean: f64,
+  pub variance_ratio_after_stdev: f64,
+}
+
+/// Across-iteration match counts of one exact-matching stratum.
+#[derive(Debug, Serialize, Clone)]
+pub struct StratumSummary {
+  pub stratum: String,
+  pub matched_mean: f64,
+  pub unmatched_mean: f64,
+}
+
+/// Across-iteration mean and standard deviation of one per-iteration
+/// statistic, over the iterations where it was finite.
+#[derive(Debug, Serialize, Clone)]
+pub struct StatisticSummary {
+  pub name: String,
+  pub mean: f64,
+  pub stdev: f64,
+  /// Iterations with a finite value.
+  pub count: usize,
+}
+
+// The mean, standard deviation and number of the finite `values`.
+fn finite_summary(values: &[f64]) -> (f64, f64, usize) {
+  let finite = values
+    .iter()
+    .cloned()
+    .filter(|x| x.is_finite())
+    .collect::<Vec<f64>>();
+  (mean(&finite[..]), sample_stdev(&finite), finite.len())
+}
+
+/// How often one test of an outcome was significant at one threshold across
+/// iterations.
+#[derive(Debug, Serialize, Clone)]
+pub struct Significance {
+  pub test: Test,
+  /// Significant means a p-value below this.
+  pub alpha: f64,
+  /// Iterations the test ran in; it is skipped with fewer than two pairs.
+  pub tested: usize,
+  /// Share of the tested iterations.
+  pub proportion_significant: f64,
+  /// Bounds of the 95% Wilson interval of the proportion, given the finite
+  /// number of iterations.
+  pub ci_lower: f64,
+  pub ci_upper: f64,
+  /// What the proportion looks like without a true effect, once calibrated
+  /// (see `calibrate`).
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub null: Option<NullSummary>,
+}
+
+// How often each test of outcome `k` was significant in `outputs` at each of
+// `alphas`.
+pub(crate) fn significance(outputs: &[Output], k: usize, alphas: &[f64]) -> Vec<Significance> {
+  let mut significance: Vec<Significance> = Vec::new();
+  for t in 0..outputs[0].outcomes[k].tests.len() {
+    for &alpha in alphas.iter() {
+      let tested = outputs
+        .iter()
+        .filter(|e| !e.outcomes[k].tests[t].pvalue.is_nan())
+        .count();
+      let significant = outputs
+        .iter()
+        .filter(|e| e.outcomes[k].tests[t].pvalue < alpha)
+        .count();
+      let (ci_lower, ci_upper) = wilson_interval(significant, tested);
+
+      significance.push(Significance {
+        test: outputs[0].outcomes[k].tests[t].test,
+        alpha,
+        tested,
+        proportion_significant: significant as f64 / tested as f64,
+        ci_lower,
+        ci_upper,
+        null: None,
+      });
+    }
+  }
+
+  significance
+}
+
+/// Across-iteration summary of one outcome: every statistic of
+/// `OutcomeStats::statistics`, and how often each of its tests was
+/// significant at each threshold.
+#[derive(Debug, Serialize, Clone)]
+pub struct OutcomeSummary {
+  pub outcome: String,
+  pub statistics: Vec<StatisticSummary>,
+  pub significance: Vec<Significance>,
+}
+
+/// Means and standard deviations of the iteration results across iterations.
+#[derive(Debug, Serialize, Clone)]
+pub struct Summary {
+  pub arm: String,
+  pub iterations: usize,
+  pub unmatched_mean: f64,
+  pub outcomes: Vec<OutcomeSummary>,
+  pub balance: Vec<BalanceSummary>,
+  pub strata: Vec<StratumSummary>,
+  /// How often each control was selected, when the iterations kept their
+  /// selections.
+  pub control_reuse: Option<ControlReuse>,
+}
+
+impl Summary {
+  /// Summarizes `outputs`, the iterations run on `dataset`, counting p-values
+  /// below each of `alphas` as significant, or `None` if there are fewer than
+  /// two to summarize across.
+  pub fn new(dataset: &Dataset, outputs: &[Output], alphas: &[f64]) -> Option<Summary> {
+    if outputs.len() < 2 {
+      return None;
+    }
+
+    let outcomes = (0..outputs[0].outcomes.len())
+      .map(|k| {
+        let per_iteration = outputs
+          .iter()
+          .map(|e| e.outcomes[k].statistics())
+          .collect::<Vec<Vec<(&str, f64)>>>();
+        let statistics = (0..per_iteration[0].len())
+          .map(|s| {
+            let values = per_iteration.iter().map(|e| e[s].1).collect::<Vec<f64>>();
+            let (mean, stdev, count) = finite_summary(&values);
+            StatisticSummary {
+              name: per_iteration[0][s].0.to_string(),
+              mean,
+              stdev,
+              count,
+            }
+          })
+          .collect();
+
+        OutcomeSummary {
+          outcome: outputs[0].outcomes[k].outcome.clone(),
+          statistics,
+          significance: significance(outputs, k, alphas),
+        }
+      })
+      .collect();
+
+    let balance = (0..outputs[0].balance.len())
+      .map(|k| {
+        let balances = outputs
+          .iter()
+          .map(|e| &e.balance[k])
+          .collect::<Vec<&Balance>>();
+        let smd_after = balances.iter().map(|b| b.smd_after).collect::<Vec<f64>>();
+        let variance_ratio_after = balances
+          .iter()
+          .map(|b| b.variance_ratio_after)
+          .collect::<Vec<f64>>();
+
+        let (smd_after_mean, smd_after_stdev, _) = finite_summary(&smd_after);
+        let (variance_ratio_after_mean, variance_ratio_after_stdev, _) =
+          finite_summary(&variance_ratio_after);
+
+        BalanceSummary {
+          covariate: balances[0].covariate.clone(),
+          smd_before: balances[0].smd_before,
+          smd_after_mean,
+          smd_after_stdev,
+          smd_after_max_abs: smd_after.iter().fold(0.0, |max: f64, d| max.max(d.abs())),
+          variance_ratio_before: balances[0].variance_ratio_before,
+          variance_ratio_after_mean,
+          variance_ratio_after_stdev,
+        }
+      })
+      .collect();
+
+    let mut per_stratum: BTreeMap<&str, (Vec<f64>, Vec<f64>)> = BTreeMap::new();
+    for count in outputs.iter().flat_map(|e| e.strata.iter()) {
+      let entry = per_stratum.entry(&count.stratum).or_default();
+      entry.0.push(count.matched as f64);
+      entry.1.push(count.unmatched as f64);
+    }
+    let strata = per_stratum
+      .into_iter()
+      .map(|(stratum, (matched, unmatched))| StratumSummary {
+        stratum: stratum.to_string(),
+        matched_mean: mean(&matched[..]),
+        unmatched_mean: mean(&unmatched[..]),
+      })
+      .collect();
+
+    Some(Summary {
+      arm: outputs[0].arm.clone(),
+      iterations: outputs.len(),
+      unmatched_mean: mean(
+        &outputs
+          .iter()
+          .map(|e| e.unmatched as f64)
+          .collect::<Vec<f64>>()[..],
+      ),
+      outcomes,
+      balance,
+      strata,
+      control_reuse: outputs
+        .iter()
+        .map(|e| e.selected.as_deref())
+        .collect::<Option<Vec<&[u64]>>>()
+        .map(|sets| control_reuse(dataset, &sets)),
+    })
+  }
+
+  /// Attaches the null distribution of every proportion significant, from
+  /// the replicates of `calibrate`.
+  pub fn add_null(&mut self, null: &[NullProportion]) {
+    for outcome in self.outcomes.iter_mut() {
+      let name = &outcome.outcome;
+      for tested in outcome.significance.iter_mut() {
+        let proportions = null
+          .iter()
+          .filter(|e| &e.outcome == name && e.test == tested.test && e.alpha == tested.alpha)
+          .map(|e| e.proportion_significant)
+          .collect::<Vec<f64>>();
+        if !proportions.is_empty() {
+          tested.null = Some(NullSummary::new(
+            tested.proportion_significant,
+            &proportions,
+          ));
+        }
+      }
+    }
+  }
+}
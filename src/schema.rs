@@ -0,0 +1,97 @@
+use crate::error::{Error, Result};
+use std::fs;
+
+/// Which input columns play which role. Columns not named here are not read.
+#[derive(Debug, Clone)]
+pub struct Schema {
+  /// Column holding each record's condition label.
+  pub group: String,
+  /// Column identifying each record in the matched pairs; records are
+  /// identified by their data row (1 for the first) when unset.
+  pub id: Option<String>,
+  /// Covariates to match on.
+  pub covariates: Vec<String>,
+  /// Columns compared, and tested, between the matched groups.
+  pub outcomes: Vec<String>,
+  /// Categorical columns a record must share with its controls.
+  pub strata: Vec<String>,
+  /// Group label of the comparison pool every treatment arm is matched
+  /// against.
+  pub control: String,
+  /// Group labels matched against the controls, each as its own arm. When
+  /// empty, every record that is not a control is pooled into a single arm.
+  pub treatments: Vec<String>,
+}
+
+impl Default for Schema {
+  fn default() -> Schema {
+    Schema {
+      group: "condition".to_string(),
+      id: None,
+      covariates: vec!["pre".to_string()],
+      outcomes: ["mid", "gain", "final"]
+        .iter()
+        .map(|c| c.to_string())
+        .collect(),
+      strata: Vec::new(),
+      control: "Big-Group".to_string(),
+      treatments: Vec::new(),
+    }
+  }
+}
+
+impl Schema {
+  /// Reads a schema from `role = columns` lines, where the role is `group`,
+  /// `id`, `covariates`, `outcomes` or `strata` and several columns are
+  /// separated by commas.
+  /// The `control` and `treatments` lines give group labels the same way.
+  /// Blank lines and lines starting with `#` are skipped, and roles the file
+  /// does not mention keep their default.
+  pub fn from_file(filename: &str) -> Result<Schema> {
+    let mut schema = Schema::default();
+    let text = fs::read_to_string(filename).map_err(|e| Error::io(filename, e))?;
+
+    for (n, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      let problem = |problem: String| Error::Schema {
+        file: filename.to_string(),
+        line: n + 1,
+        problem,
+      };
+      let at = line
+        .find('=')
+        .ok_or_else(|| problem("expected `role = columns`".to_string()))?;
+      let role = line[..at].trim();
+      let columns = split_columns(&line[at + 1..]);
+      if columns.is_empty() {
+        return Err(problem(format!("no columns given for `{}`", role)));
+      }
+
+      match role {
+        "group" => schema.group = columns[0].clone(),
+        "id" => schema.id = Some(columns[0].clone()),
+        "covariates" => schema.covariates = columns,
+        "outcomes" => schema.outcomes = columns,
+        "strata" => schema.strata = columns,
+        "control" => schema.control = columns[0].clone(),
+        "treatments" => schema.treatments = columns,
+        _ => return Err(problem(format!("unknown role `{}`", role))),
+      }
+    }
+
+    Ok(schema)
+  }
+}
+
+/// Splits a comma-separated list of column names.
+pub fn split_columns(columns: &str) -> Vec<String> {
+  columns
+    .split(',')
+    .map(|column| column.trim().to_string())
+    .filter(|column| !column.is_empty())
+    .collect()
+}
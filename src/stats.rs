@@ -0,0 +1,663 @@
+use rand::Rng;
+use serde::Serialize;
+use statistical::{mean, variance};
+use statrs::distribution::{InverseCDF, Normal, StudentsT, Univariate};
+use statrs::function::factorial::ln_binomial;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// The alternative hypothesis of the paired t-test, stated in terms of the
+/// Small-Group outcome relative to their matched Big-Group outcome.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub enum Alternative {
+  #[serde(rename = "two-sided")]
+  TwoSided,
+  #[serde(rename = "small-greater")]
+  SmallGreater,
+  #[serde(rename = "small-less")]
+  SmallLess,
+}
+
+impl Alternative {
+  pub fn from_arg(arg: &str) -> Alternative {
+    match arg {
+      "small-greater" => Alternative::SmallGreater,
+      "small-less" => Alternative::SmallLess,
+      _ => Alternative::TwoSided,
+    }
+  }
+}
+
+impl fmt::Display for Alternative {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let arg = match self {
+      Alternative::TwoSided => "two-sided",
+      Alternative::SmallGreater => "small-greater",
+      Alternative::SmallLess => "small-less",
+    };
+    write!(f, "{}", arg)
+  }
+}
+
+/// A test of the differences between matched pairs.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub enum Test {
+  /// Paired t-test.
+  #[serde(rename = "t")]
+  T,
+  /// Wilcoxon signed-rank test.
+  #[serde(rename = "wilcoxon")]
+  Wilcoxon,
+  /// Sign test.
+  #[serde(rename = "sign")]
+  Sign,
+  /// Sign-flip permutation test of the mean difference.
+  #[serde(rename = "permutation")]
+  Permutation,
+}
+
+impl Test {
+  pub fn from_arg(arg: &str) -> Test {
+    match arg {
+      "wilcoxon" => Test::Wilcoxon,
+      "sign" => Test::Sign,
+      "permutation" => Test::Permutation,
+      _ => Test::T,
+    }
+  }
+
+  /// Name of the test's p-value among the outcome statistics.
+  pub fn pvalue_name(self) -> &'static str {
+    match self {
+      Test::T => "t_pvalue",
+      Test::Wilcoxon => "wilcoxon_pvalue",
+      Test::Sign => "sign_pvalue",
+      Test::Permutation => "permutation_pvalue",
+    }
+  }
+
+  /// Name of the test's statistic among the outcome statistics: t, the
+  /// signed-rank sum W+, the number of positive differences, or the mean
+  /// difference.
+  pub fn statistic_name(self) -> &'static str {
+    match self {
+      Test::T => "t_tvalue",
+      Test::Wilcoxon => "wilcoxon_w",
+      Test::Sign => "sign_positive",
+      Test::Permutation => "permutation_difference",
+    }
+  }
+
+  /// Runs the test on the pairs of `a` and `b`. The permutation test draws
+  /// its `permutations` sign flips from `rng`.
+  pub fn run<R: Rng>(
+    self,
+    a: &[f64],
+    b: &[f64],
+    alternative: Alternative,
+    permutations: usize,
+    rng: &mut R,
+  ) -> TestOutcome {
+    match self {
+      Test::T => {
+        let result = paired_t(a.to_vec(), b.to_vec(), alternative);
+        TestOutcome {
+          test: self,
+          pvalue: result.p,
+          statistic: result.t,
+        }
+      }
+      Test::Wilcoxon => wilcoxon_signed_rank(a, b, alternative),
+      Test::Sign => sign_test(a, b, alternative),
+      Test::Permutation => sign_flip_permutation(a, b, alternative, permutations, rng),
+    }
+  }
+}
+
+impl fmt::Display for Test {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let arg = match self {
+      Test::T => "t",
+      Test::Wilcoxon => "wilcoxon",
+      Test::Sign => "sign",
+      Test::Permutation => "permutation",
+    };
+    write!(f, "{}", arg)
+  }
+}
+
+/// The 95% Wilson score interval of a proportion of `successes` out of
+/// `trials`.
+pub fn wilson_interval(successes: usize, trials: usize) -> (f64, f64) {
+  let z = Normal::new(0.0, 1.0).unwrap().inverse_cdf(0.975);
+  let n = trials as f64;
+  let p = successes as f64 / n;
+
+  let center = (p + z * z / (2.0 * n)) / (1.0 + z * z / n);
+  let half_width = z / (1.0 + z * z / n) * (p * (1.0 - p) / n + z * z / (4.0 * n * n)).sqrt();
+
+  // The bounds are exactly 0 and 1 at the extremes, whatever the rounding
+  let lower = if successes == 0 {
+    0.0
+  } else {
+    center - half_width
+  };
+  let upper = if successes == trials {
+    1.0
+  } else {
+    center + half_width
+  };
+
+  (lower, upper)
+}
+
+/// The p-value and statistic of one test of the matched differences.
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct TestOutcome {
+  pub test: Test,
+  pub pvalue: f64,
+  pub statistic: f64,
+}
+
+// Combines the one-sided p-values P(statistic >= observed) and
+// P(statistic <= observed) for `alternative`.
+fn one_or_two_sided(upper: f64, lower: f64, alternative: Alternative) -> f64 {
+  match alternative {
+    Alternative::TwoSided => (2.0 * upper.min(lower)).min(1.0),
+    Alternative::SmallGreater => upper,
+    Alternative::SmallLess => lower,
+  }
+}
+
+// The nonzero differences between the pairs of `a` and `b`.
+fn nonzero_differences(a: &[f64], b: &[f64]) -> Vec<f64> {
+  a.iter()
+    .zip(b.iter())
+    .map(|(a, b)| a - b)
+    .filter(|d| *d != 0.0)
+    .collect()
+}
+
+/// The Wilcoxon signed-rank test on the differences between the pairs of `a`
+/// and `b`, leaving out zero differences. The p-value is exact for up to 50
+/// differences, ties included, and otherwise from the normal approximation
+/// with the tie correction to the variance.
+pub fn wilcoxon_signed_rank(a: &[f64], b: &[f64], alternative: Alternative) -> TestOutcome {
+  let mut d = nonzero_differences(a, b);
+  d.sort_by(|x, y| x.abs().partial_cmp(&y.abs()).unwrap_or(Ordering::Equal));
+  let n = d.len();
+
+  // Rank the absolute differences, giving ties their average rank. Doubled,
+  // the ranks are whole numbers even with ties.
+  let mut doubled_ranks: Vec<usize> = Vec::with_capacity(n);
+  let mut w_plus = 0.0;
+  let mut tie_correction = 0.0;
+  let mut i = 0;
+  while i < n {
+    let mut j = i;
+    while j + 1 < n && d[j + 1].abs() == d[i].abs() {
+      j += 1;
+    }
+    let rank = (i + j + 2) as f64 / 2.0;
+    w_plus += d[i..=j].iter().filter(|x| **x > 0.0).count() as f64 * rank;
+    for _ in i..=j {
+      doubled_ranks.push(i + j + 2);
+    }
+    let t = (j - i + 1) as f64;
+    tie_correction += t * t * t - t;
+    i = j + 1;
+  }
+
+  let pvalue = if n == 0 {
+    f64::NAN
+  } else if n <= 50 {
+    // Exact null distribution of 2 W+, conditional on the ranks: each rank is
+    // positive with probability 1/2, independently
+    let max = n * (n + 1);
+    let mut distribution = vec![0.0; max + 1];
+    distribution[0] = 1.0;
+    for &rank in doubled_ranks.iter() {
+      for w in (rank..=max).rev() {
+        distribution[w] = (distribution[w] + distribution[w - rank]) / 2.0;
+      }
+      for p in distribution[..rank].iter_mut() {
+        *p /= 2.0;
+      }
+    }
+    let w = (2.0 * w_plus).round() as usize;
+    one_or_two_sided(
+      distribution[w..].iter().sum(),
+      distribution[..=w].iter().sum(),
+      alternative,
+    )
+  } else {
+    let n = n as f64;
+    let mean = n * (n + 1.0) / 4.0;
+    let variance = n * (n + 1.0) * (2.0 * n + 1.0) / 24.0 - tie_correction / 48.0;
+    let z = (w_plus - mean) / variance.sqrt();
+    let normal = Normal::new(0.0, 1.0).unwrap();
+    one_or_two_sided(1.0 - normal.cdf(z), normal.cdf(z), alternative)
+  };
+
+  TestOutcome {
+    test: Test::Wilcoxon,
+    pvalue,
+    statistic: w_plus,
+  }
+}
+
+/// The sign test on the differences between the pairs of `a` and `b`: the
+/// number of positive differences against a fair binomial over the nonzero
+/// ones.
+pub fn sign_test(a: &[f64], b: &[f64], alternative: Alternative) -> TestOutcome {
+  let d = nonzero_differences(a, b);
+  let n = d.len() as u64;
+  let positive = d.iter().filter(|x| **x > 0.0).count() as u64;
+
+  let probability = |k: u64| (ln_binomial(n, k) - n as f64 * 2f64.ln()).exp();
+  let pvalue = if n == 0 {
+    f64::NAN
+  } else {
+    one_or_two_sided(
+      (positive..=n).map(probability).sum::<f64>().min(1.0),
+      (0..=positive).map(probability).sum::<f64>().min(1.0),
+      alternative,
+    )
+  };
+
+  TestOutcome {
+    test: Test::Sign,
+    pvalue,
+    statistic: positive as f64,
+  }
+}
+
+/// The sign-flip permutation test on the mean difference between the pairs of
+/// `a` and `b`: under the null each difference is as likely to have either
+/// sign, so the observed mean is compared with the means of `permutations`
+/// random sign flips drawn from `rng`. The p-value counts the observed
+/// arrangement as one of the permutations, so it is never 0.
+pub fn sign_flip_permutation<R: Rng>(
+  a: &[f64],
+  b: &[f64],
+  alternative: Alternative,
+  permutations: usize,
+  rng: &mut R,
+) -> TestOutcome {
+  let d = a
+    .iter()
+    .zip(b.iter())
+    .map(|(a, b)| a - b)
+    .collect::<Vec<f64>>();
+  let observed = d.iter().sum::<f64>();
+  // Rearranged sums can differ from the observed one by rounding alone
+  let tolerance = 1e-9 * d.iter().map(|x| x.abs()).sum::<f64>();
+
+  let mut extreme = 0;
+  for _ in 0..permutations {
+    let sum = d
+      .iter()
+      .map(|x| if rng.gen::<bool>() { *x } else { -x })
+      .sum::<f64>();
+    let as_extreme = match alternative {
+      Alternative::TwoSided => sum.abs() >= observed.abs() - tolerance,
+      Alternative::SmallGreater => sum >= observed - tolerance,
+      Alternative::SmallLess => sum <= observed + tolerance,
+    };
+    if as_extreme {
+      extreme += 1;
+    }
+  }
+
+  TestOutcome {
+    test: Test::Permutation,
+    pvalue: if d.is_empty() {
+      f64::NAN
+    } else {
+      (extreme + 1) as f64 / (permutations + 1) as f64
+    },
+    statistic: observed / d.len() as f64,
+  }
+}
+
+// The probability, under the null of no mean difference, of a t at least as
+// extreme as the observed one in the direction(s) of `alternative`.
+pub(crate) fn t_pvalue(t: f64, dof: f64, alternative: Alternative) -> f64 {
+  if t.is_nan() {
+    return f64::NAN;
+  }
+  let t_tester = StudentsT::new(0.0, 1.0, dof).unwrap();
+  match alternative {
+    Alternative::TwoSided => 2.0 * t_tester.cdf(-t.abs()),
+    Alternative::SmallGreater => 1.0 - t_tester.cdf(t),
+    Alternative::SmallLess => t_tester.cdf(t),
+  }
+}
+
+// The `p` quantile of Student's t distribution with `dof` degrees of freedom,
+// found by bisection on the CDF.
+pub(crate) fn t_quantile(p: f64, dof: f64) -> f64 {
+  let t_tester = StudentsT::new(0.0, 1.0, dof).unwrap();
+  let (mut lo, mut hi) = (-1.0, 1.0);
+  while t_tester.cdf(lo) > p {
+    lo *= 2.0;
+  }
+  while t_tester.cdf(hi) < p {
+    hi *= 2.0;
+  }
+  for _ in 0..100 {
+    let mid = (lo + hi) / 2.0;
+    if t_tester.cdf(mid) < p {
+      lo = mid;
+    } else {
+      hi = mid;
+    }
+  }
+  (lo + hi) / 2.0
+}
+
+/// The sample variance of `xs`, or NaN with fewer than two values or a NaN
+/// among them.
+pub fn sample_variance(xs: &[f64]) -> f64 {
+  if xs.len() < 2 || xs.iter().any(|x| x.is_nan()) {
+    f64::NAN
+  } else {
+    variance(xs, None)
+  }
+}
+
+/// The sample standard deviation of `xs`, or NaN as for `sample_variance`.
+pub fn sample_stdev(xs: &[f64]) -> f64 {
+  sample_variance(xs).sqrt()
+}
+
+// `x` in units of `scale`, or NaN when the scale is 0 and there is nothing to
+// measure against.
+fn standardized(x: f64, scale: f64) -> f64 {
+  if scale > 0.0 {
+    x / scale
+  } else {
+    f64::NAN
+  }
+}
+
+/// Effect sizes of the difference between paired observations, Small-Group
+/// minus Big-Group.
+#[derive(Debug, Clone, Copy)]
+pub struct EffectSize {
+  pub mean_difference: f64,
+  /// Bounds of the 95% confidence interval of the mean difference.
+  pub ci_lower: f64,
+  pub ci_upper: f64,
+  /// Cohen's d_z: the mean difference over the standard deviation of the
+  /// differences.
+  pub d_z: f64,
+  /// Cohen's d_av: the mean difference over the average of the two groups'
+  /// standard deviations.
+  pub d_av: f64,
+  /// Hedges' g: d_av corrected for its small-sample bias.
+  pub hedges_g: f64,
+}
+
+/// Every effect size is NaN with fewer than two pairs, and the standardized
+/// ones also when the standard deviation they divide by is 0.
+pub fn paired_effect_size(a: &[f64], b: &[f64]) -> EffectSize {
+  let n = a.len();
+  if n < 2 {
+    return EffectSize {
+      mean_difference: f64::NAN,
+      ci_lower: f64::NAN,
+      ci_upper: f64::NAN,
+      d_z: f64::NAN,
+      d_av: f64::NAN,
+      hedges_g: f64::NAN,
+    };
+  }
+  let dof = (n - 1) as f64;
+
+  let d = a
+    .iter()
+    .zip(b.iter())
+    .map(|(a, b)| a - b)
+    .collect::<Vec<f64>>();
+  let dbar = mean(&d[..]);
+  let sd = sample_stdev(&d);
+
+  let margin = t_quantile(0.975, dof) * sd / (n as f64).sqrt();
+  let sd_av = (sample_stdev(a) + sample_stdev(b)) / 2.0;
+  let d_av = standardized(dbar, sd_av);
+
+  EffectSize {
+    mean_difference: dbar,
+    ci_lower: dbar - margin,
+    ci_upper: dbar + margin,
+    d_z: standardized(dbar, sd),
+    d_av,
+    hedges_g: d_av * (1.0 - 3.0 / (4.0 * dof - 1.0)),
+  }
+}
+
+/// The p-value and t statistic of a t-test.
+#[derive(Debug, Clone, Copy)]
+pub struct TTestResult {
+  pub p: f64,
+  pub t: f64,
+}
+
+/// The paired t-test of `a` against `b`; NaN with fewer than two pairs or
+/// when every difference is the same.
+pub fn paired_t(a: Vec<f64>, b: Vec<f64>, alternative: Alternative) -> TTestResult {
+  let n = a.len();
+  if n < 2 {
+    return TTestResult {
+      p: f64::NAN,
+      t: f64::NAN,
+    };
+  }
+
+  let d = a
+    .iter()
+    .zip(b.iter())
+    .map(|(a, b)| a - b)
+    .collect::<Vec<f64>>();
+  let dbar = mean(&d[..]);
+  let sd = sample_stdev(&d);
+
+  let se_dbar = sd / (n as f64).sqrt();
+
+  let t = standardized(dbar, se_dbar);
+
+  let p = t_pvalue(t, (n - 1) as f64, alternative);
+
+  TTestResult { p, t }
+}
+
+/// The t-test on the group coefficient of a weighted least-squares regression
+/// of value on Small-Group membership, from (value, weight) observations.
+/// Observations with zero weight are left out, including from the degrees of
+/// freedom. NaN unless each group has a weighted observation and there are at
+/// least three in all, and when neither group varies.
+pub fn weighted_t(a: Vec<(f64, f64)>, b: Vec<(f64, f64)>, alternative: Alternative) -> TTestResult {
+  let a = a
+    .into_iter()
+    .filter(|(_, w)| *w > 0.0)
+    .collect::<Vec<(f64, f64)>>();
+  let b = b
+    .into_iter()
+    .filter(|(_, w)| *w > 0.0)
+    .collect::<Vec<(f64, f64)>>();
+  if a.is_empty() || b.is_empty() || a.len() + b.len() < 3 {
+    return TTestResult {
+      p: f64::NAN,
+      t: f64::NAN,
+    };
+  }
+
+  let weighted_mean = |xs: &[(f64, f64)]| {
+    xs.iter().map(|(x, w)| x * w).sum::<f64>() / xs.iter().map(|(_, w)| w).sum::<f64>()
+  };
+  let a_mean = weighted_mean(&a);
+  let b_mean = weighted_mean(&b);
+
+  let residual_ss = a.iter().map(|(x, w)| w * (x - a_mean).powi(2)).sum::<f64>()
+    + b.iter().map(|(x, w)| w * (x - b_mean).powi(2)).sum::<f64>();
+  let dof = (a.len() + b.len() - 2) as f64;
+  let sigma_squared = residual_ss / dof;
+
+  let a_weight: f64 = a.iter().map(|(_, w)| w).sum();
+  let b_weight: f64 = b.iter().map(|(_, w)| w).sum();
+  let se = (sigma_squared * (1.0 / a_weight + 1.0 / b_weight)).sqrt();
+
+  let t = standardized(a_mean - b_mean, se);
+
+  let p = t_pvalue(t, dof, alternative);
+
+  TTestResult { p, t }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-12
+  }
+
+  // For values from tables, or from distribution functions computed to less
+  // than full precision
+  fn near(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-6
+  }
+
+  #[test]
+  fn exact_wilcoxon_of_five_positive_differences() {
+    let a = [1.0, 2.0, 3.0, 4.0, 5.0];
+    let b = [0.0; 5];
+
+    let greater = wilcoxon_signed_rank(&a, &b, Alternative::SmallGreater);
+    assert_eq!(greater.statistic, 15.0);
+    assert!(close(greater.pvalue, 1.0 / 32.0));
+    let two_sided = wilcoxon_signed_rank(&a, &b, Alternative::TwoSided);
+    assert!(close(two_sided.pvalue, 1.0 / 16.0));
+    let less = wilcoxon_signed_rank(&a, &b, Alternative::SmallLess);
+    assert!(close(less.pvalue, 1.0));
+  }
+
+  #[test]
+  fn exact_wilcoxon_with_tied_ranks() {
+    // Midranks 1.5, 1.5, 3.5, 3.5; of the 16 sign patterns, W+ >= 8.5 in
+    // {1.5, 3.5, 3.5} twice and {1.5, 1.5, 3.5, 3.5} once, and W+ <= 8.5 in all
+    // but the last
+    let a = [1.0, -1.0, 2.0, 2.0];
+    let b = [0.0; 4];
+
+    let greater = wilcoxon_signed_rank(&a, &b, Alternative::SmallGreater);
+    assert_eq!(greater.statistic, 8.5);
+    assert!(close(greater.pvalue, 3.0 / 16.0));
+    let less = wilcoxon_signed_rank(&a, &b, Alternative::SmallLess);
+    assert!(close(less.pvalue, 15.0 / 16.0));
+  }
+
+  #[test]
+  fn sign_test_matches_binomial_tables() {
+    // 8 of 10 positive: P(X >= 8) = (45 + 10 + 1) / 1024
+    let a = [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, -1.0, -1.0, 0.0];
+    let b = [0.0; 11];
+    let greater = sign_test(&a, &b, Alternative::SmallGreater);
+    assert_eq!(greater.statistic, 8.0);
+    assert!(close(greater.pvalue, 56.0 / 1024.0));
+    let two_sided = sign_test(&a, &b, Alternative::TwoSided);
+    assert!(close(two_sided.pvalue, 112.0 / 1024.0));
+    let less = sign_test(&a, &b, Alternative::SmallLess);
+    assert!(close(less.pvalue, 1013.0 / 1024.0));
+
+    // 15 of 20 positive: P(X >= 15) = 21700 / 2^20
+    let a = (0..20)
+      .map(|i| if i < 15 { 1.0 } else { -1.0 })
+      .collect::<Vec<f64>>();
+    let b = vec![0.0; 20];
+    let greater = sign_test(&a, &b, Alternative::SmallGreater);
+    assert!(close(greater.pvalue, 21700.0 / 1048576.0));
+  }
+
+  #[test]
+  fn t_pvalues_match_the_t_distribution() {
+    // t = 2 on 10 degrees of freedom
+    assert!(near(
+      t_pvalue(2.0, 10.0, Alternative::TwoSided),
+      0.073388035
+    ));
+    assert!(near(
+      t_pvalue(2.0, 10.0, Alternative::SmallGreater),
+      0.036694017
+    ));
+    assert!(near(
+      t_pvalue(2.0, 10.0, Alternative::SmallLess),
+      0.963305983
+    ));
+    assert!(near(
+      t_pvalue(-2.0, 10.0, Alternative::SmallLess),
+      0.036694017
+    ));
+    assert!(near(t_quantile(0.975, 10.0), 2.228138852));
+  }
+
+  #[test]
+  fn paired_t_of_known_differences() {
+    // Differences 2, 3, 1, 3, 1: mean 2, standard deviation 1, so t = sqrt(20)
+    // on 4 degrees of freedom
+    let a = vec![5.0, 7.0, 6.0, 9.0, 8.0];
+    let b = vec![3.0, 4.0, 5.0, 6.0, 7.0];
+
+    let result = paired_t(a.clone(), b.clone(), Alternative::TwoSided);
+    assert!(close(result.t, 20f64.sqrt()));
+    assert!(near(result.p, 0.011056493));
+    let greater = paired_t(a, b, Alternative::SmallGreater);
+    assert!(near(greater.p, 0.011056493 / 2.0));
+
+    let result = paired_t(vec![1.0, 2.0], vec![0.0, 1.0], Alternative::TwoSided);
+    assert!(result.t.is_nan() && result.p.is_nan());
+  }
+
+  #[test]
+  fn effect_sizes_of_known_differences() {
+    // Differences 2, 3, 1, 3, 1 (mean 2, sd 1); both groups have sd sqrt(2.5)
+    let a = [5.0, 7.0, 6.0, 9.0, 8.0];
+    let b = [3.0, 4.0, 5.0, 6.0, 7.0];
+    let effect = paired_effect_size(&a, &b);
+
+    assert!(close(effect.mean_difference, 2.0));
+    // 2 -/+ t(0.975, 4) / sqrt(5), with t(0.975, 4) = 2.776445105
+    assert!(near(effect.ci_lower, 2.0 - 2.776445105 / 5f64.sqrt()));
+    assert!(near(effect.ci_upper, 2.0 + 2.776445105 / 5f64.sqrt()));
+    assert!(close(effect.d_z, 2.0));
+    assert!(close(effect.d_av, 2.0 / 2.5f64.sqrt()));
+    // Hedges' correction on 4 degrees of freedom is 1 - 3 / 15
+    assert!(close(effect.hedges_g, 0.8 * 2.0 / 2.5f64.sqrt()));
+  }
+
+  #[test]
+  fn effect_sizes_without_spread_are_nan() {
+    let effect = paired_effect_size(&[3.0, 4.0, 5.0], &[2.0, 3.0, 4.0]);
+    assert!(close(effect.mean_difference, 1.0));
+    assert!(effect.d_z.is_nan());
+    assert!(close(effect.d_av, 1.0));
+
+    let effect = paired_effect_size(&[3.0], &[2.0]);
+    assert!(effect.mean_difference.is_nan() && effect.ci_lower.is_nan() && effect.d_z.is_nan());
+  }
+
+  #[test]
+  fn wilson_intervals_match_known_values() {
+    let (lower, upper) = wilson_interval(5, 10);
+    assert!(near(lower, 0.236593091) && near(upper, 0.763406909));
+    let (lower, upper) = wilson_interval(3, 20);
+    assert!(near(lower, 0.052368746) && near(upper, 0.360418865));
+
+    // Exact bounds at the extremes
+    let (lower, upper) = wilson_interval(0, 10);
+    assert!(lower == 0.0 && near(upper, 0.277532800));
+    let (lower, upper) = wilson_interval(10, 10);
+    assert!(near(lower, 0.722467200) && upper == 1.0);
+  }
+}